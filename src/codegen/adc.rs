@@ -0,0 +1,343 @@
+use crate::codegen::{cfg::Universe, emit, mcu_features};
+use crate::cubemx::mcu;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pin(char, u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    /// Single-ended input, or the positive input of a differential pair (`INPx`).
+    Positive,
+    /// Negative input of a differential pair (`INNx`).
+    Negative,
+}
+
+/// Conversion speed of an input marked as fast (`ADCx_INyf`) or slow
+/// (`ADCx_INys`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed {
+    Fast,
+    Slow,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Channel {
+    pub polarity: Polarity,
+    pub adc: String,
+    pub nr: u8,
+    /// `None` for inputs without a speed marker.
+    pub speed: Option<Speed>,
+}
+
+pub type Channels = BTreeMap<Pin, BTreeSet<Channel>>;
+
+/// One ADC input of a pin for one HAL chip feature, as emitted by `--format`.
+#[derive(Debug, Serialize)]
+pub struct ChannelRecord {
+    pub feature: String,
+    pub pin: String,
+    pub adc: String,
+    pub channel: u8,
+    pub polarity: Polarity,
+    pub speed: Option<Speed>,
+}
+
+/// Returns the ADC channels of every HAL chip feature.
+pub fn feature_channels(mcus: &[mcu::Mcu]) -> Result<BTreeMap<String, Channels>> {
+    // The CubeMX database distinguishes far more chip variants than the HAL
+    // features do. For every feature pick the variant with the most channels,
+    // even if that allows a bit more than some chips of the feature support.
+    let mut by_feature = BTreeMap::<String, Channels>::new();
+    for mcu in mcus {
+        let features = match mcu_features(&mcu.ref_name) {
+            Ok(features) => features,
+            Err(e) => {
+                eprintln!("skipping {}: {:#}", mcu.ref_name, e);
+                continue;
+            }
+        };
+        let channels = mcu_channels(mcu)?;
+        for feature in features {
+            let entry = by_feature.entry(feature).or_default();
+            if count(&channels) > count(entry) {
+                *entry = channels.clone();
+            }
+        }
+    }
+    Ok(by_feature)
+}
+
+pub fn records(mcus: &[mcu::Mcu]) -> Result<Vec<ChannelRecord>> {
+    let mut records = Vec::new();
+    for (feature, channels) in feature_channels(mcus)? {
+        for (pin, chs) in &channels {
+            for c in chs {
+                records.push(ChannelRecord {
+                    feature: feature.clone(),
+                    pin: format!("P{}{}", pin.0, pin.1),
+                    adc: c.adc.clone(),
+                    channel: c.nr,
+                    polarity: c.polarity,
+                    speed: c.speed,
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+pub fn gen_channels(mcus: &[mcu::Mcu], complete: bool) -> Result<String> {
+    let by_feature = feature_channels(mcus)?;
+    let universe = Universe::new(by_feature.keys().cloned()).complete(complete);
+    let mut tables = Vec::<(Vec<String>, Channels)>::new();
+    for (feature, channels) in by_feature {
+        if channels.is_empty() {
+            continue;
+        }
+        if let Some((features, _)) = tables.iter_mut().find(|(_, c)| *c == channels) {
+            features.push(feature);
+        } else {
+            tables.push((vec![feature], channels));
+        }
+    }
+
+    let mut tokens = TokenStream::new();
+    for (features, channels) in &tables {
+        let cfg = universe.cfg(features);
+        for (name, polarity) in [
+            ("channel", Polarity::Positive),
+            ("channel_n", Polarity::Negative),
+        ] {
+            if let Some(invocation) = render_channels(name, channels, polarity)? {
+                tokens.extend(quote!(#cfg #invocation));
+            }
+        }
+    }
+    let mut out = String::from("\n");
+    out.push_str(&emit::format_file(tokens)?);
+    Ok(out)
+}
+
+/// Renders a `channel!` style invocation with the inputs of one polarity, or
+/// `None` if there are none.
+fn render_channels(
+    name: &str,
+    channels: &Channels,
+    polarity: Polarity,
+) -> Result<Option<TokenStream>> {
+    let mut entries = Vec::new();
+    for (pin, chs) in channels {
+        // The fast and slow input of a channel select the same channel.
+        let chs: BTreeSet<_> = chs
+            .iter()
+            .filter(|c| c.polarity == polarity)
+            .map(|c| (c.adc.as_str(), c.nr))
+            .collect();
+        let chs = chs
+            .into_iter()
+            .map(|(adc, nr)| {
+                let adc = emit::ident(adc)?;
+                let nr = Literal::u8_unsuffixed(nr);
+                Ok(quote!((#adc, #nr)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !chs.is_empty() {
+            let pin = format_ident!("P{}{}", pin.0, pin.1);
+            entries.push(quote!((#pin, [#(#chs),*])));
+        }
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let name = emit::ident(name)?;
+    Ok(Some(quote!(#name!([#(#entries,)*]);)))
+}
+
+fn count(channels: &Channels) -> usize {
+    channels.values().map(BTreeSet::len).sum()
+}
+
+pub fn mcu_channels(mcu: &mcu::Mcu) -> Result<Channels> {
+    let mut channels = Channels::new();
+    let mut skipped = BTreeSet::new();
+    for pin in &mcu.pins {
+        let name = match pin.gpio() {
+            Some(name) => name,
+            None => continue,
+        };
+        // The 32 kHz oscillator pins are not usable as analog inputs.
+        if matches!(&name.suffix, Some(suffix) if suffix.contains("OSC32")) {
+            continue;
+        }
+
+        for signal in &pin.signals {
+            match parse_signal(&signal.name)? {
+                Some(chs) => channels
+                    .entry(Pin(name.port, name.number))
+                    .or_default()
+                    .extend(chs),
+                None if is_adc_input(&signal.name) => {
+                    skipped.insert(signal.name.as_str());
+                }
+                None => {}
+            }
+        }
+    }
+    if !skipped.is_empty() {
+        let skipped: Vec<_> = skipped.into_iter().collect();
+        eprintln!(
+            "{}: skipping unknown ADC inputs {}",
+            mcu.ref_name,
+            skipped.join(", ")
+        );
+    }
+    Ok(channels)
+}
+
+/// Returns whether a signal looks like an ADC input, whether or not
+/// [`parse_signal`] understands it.
+fn is_adc_input(name: &str) -> bool {
+    static INPUT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^ADC\d*_IN").unwrap());
+    INPUT.is_match(name)
+}
+
+/// Parses ADC input signals like `ADC1_IN5`, `ADC12_INP3`, `ADC1_INN3` or
+/// the fast input `ADC1_IN1f`.
+///
+/// Signals shared between several ADCs are split into one channel per ADC.
+fn parse_signal(name: &str) -> Result<Option<Vec<Channel>>> {
+    static SIGNAL: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^ADC(?P<adcs>\d*)_IN(?P<polarity>[PN]?)(?P<nr>\d{1,2})(?P<speed>[fs]?)$")
+            .unwrap()
+    });
+
+    let captures = match SIGNAL.captures(name) {
+        Some(captures) => captures,
+        None => return Ok(None),
+    };
+    let polarity = match captures.name("polarity").unwrap().as_str() {
+        "N" => Polarity::Negative,
+        _ => Polarity::Positive,
+    };
+    let nr = captures.name("nr").unwrap().as_str().parse()?;
+    let speed = match captures.name("speed").unwrap().as_str() {
+        "f" => Some(Speed::Fast),
+        "s" => Some(Speed::Slow),
+        _ => None,
+    };
+
+    let adcs = captures.name("adcs").unwrap().as_str();
+    let adcs = if adcs.is_empty() {
+        vec!["ADC".to_string()]
+    } else {
+        adcs.chars().map(|c| format!("ADC{c}")).collect()
+    };
+
+    Ok(Some(
+        adcs.into_iter()
+            .map(|adc| Channel {
+                polarity,
+                adc,
+                nr,
+                speed,
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(adc: &str, nr: u8, polarity: Polarity, speed: Option<Speed>) -> Channel {
+        Channel {
+            polarity,
+            adc: adc.into(),
+            nr,
+            speed,
+        }
+    }
+
+    #[test]
+    fn single_ended_input() {
+        assert_eq!(
+            parse_signal("ADC1_IN5").unwrap(),
+            Some(vec![channel("ADC1", 5, Polarity::Positive, None)])
+        );
+    }
+
+    #[test]
+    fn shared_input() {
+        assert_eq!(
+            parse_signal("ADC12_INP3").unwrap(),
+            Some(vec![
+                channel("ADC1", 3, Polarity::Positive, None),
+                channel("ADC2", 3, Polarity::Positive, None),
+            ])
+        );
+    }
+
+    #[test]
+    fn negative_input() {
+        assert_eq!(
+            parse_signal("ADC1_INN3").unwrap(),
+            Some(vec![channel("ADC1", 3, Polarity::Negative, None)])
+        );
+    }
+
+    #[test]
+    fn fast_and_slow_inputs() {
+        assert_eq!(
+            parse_signal("ADC1_IN1f").unwrap(),
+            Some(vec![channel(
+                "ADC1",
+                1,
+                Polarity::Positive,
+                Some(Speed::Fast)
+            )])
+        );
+        assert_eq!(
+            parse_signal("ADC_IN10s").unwrap(),
+            Some(vec![channel(
+                "ADC",
+                10,
+                Polarity::Positive,
+                Some(Speed::Slow)
+            )])
+        );
+    }
+
+    #[test]
+    fn other_signals() {
+        assert_eq!(parse_signal("ADC1_EXTI11").unwrap(), None);
+        assert_eq!(parse_signal("USART2_TX").unwrap(), None);
+        assert_eq!(parse_signal("ADC1_IN1b").unwrap(), None);
+        assert!(is_adc_input("ADC1_IN1b"));
+        assert!(!is_adc_input("ADC1_EXTI11"));
+    }
+
+    #[test]
+    fn renders_fast_and_slow_inputs_once() {
+        let mut channels = Channels::new();
+        channels.entry(Pin('A', 0)).or_default().extend([
+            channel("ADC1", 1, Polarity::Positive, Some(Speed::Fast)),
+            channel("ADC1", 1, Polarity::Positive, Some(Speed::Slow)),
+        ]);
+        let tokens = render_channels("channel", &channels, Polarity::Positive)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            emit::format_file(tokens).unwrap(),
+            "channel!([\n    (PA0, [(ADC1, 1)]),\n]);\n"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use once_cell::sync::Lazy;
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use regex::Regex;
+use std::collections::BTreeSet;
+
+/// The features a generated file can be conditional on.
+///
+/// `cfg` attributes are only minimised against a complete set, i.e. one that
+/// covers all features of the HAL crate the code is generated for (or of the
+/// enclosing item): code present for every feature needs no attribute, code
+/// missing for only a few features is guarded by `not(...)`, and chip
+/// features covering a whole series are replaced by the series feature.
+/// Against an incomplete set, e.g. when only some MCUs of a family are
+/// selected, the features are always listed.
+#[derive(Clone, Debug, Default)]
+pub struct Universe {
+    features: BTreeSet<String>,
+    complete: bool,
+}
+
+/// A minimised `cfg` predicate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cfg {
+    /// Any of the features is enabled.
+    Any(Vec<String>),
+    /// None of the features is enabled.
+    NotAny(Vec<String>),
+}
+
+impl Universe {
+    pub fn new<I, S>(features: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            features: features.into_iter().map(Into::into).collect(),
+            complete: false,
+        }
+    }
+
+    /// Marks the set as covering every feature the code can be built with.
+    pub fn complete(mut self, complete: bool) -> Self {
+        self.complete = complete;
+        self
+    }
+
+    /// Returns the smallest predicate that holds for exactly the `selected`
+    /// features, or `None` if it holds for all of them.
+    pub fn cfg<'a, I>(&self, selected: I) -> Option<Cfg>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let selected: BTreeSet<String> = selected.into_iter().cloned().collect();
+        if !self.complete {
+            return Some(Cfg::Any(selected.into_iter().collect()));
+        }
+        if self.features.is_subset(&selected) {
+            return None;
+        }
+
+        let positive = self.cover(&selected);
+        let complement: BTreeSet<String> = self.features.difference(&selected).cloned().collect();
+        let negative = self.cover(&complement);
+        // Unknown features can only be expressed positively.
+        if negative.len() < positive.len() && selected.is_subset(&self.features) {
+            Some(Cfg::NotAny(negative))
+        } else {
+            Some(Cfg::Any(positive))
+        }
+    }
+
+    /// Lists `set` as features, replacing the chip features of a series by
+    /// the series feature if `set` holds all of them.
+    fn cover(&self, set: &BTreeSet<String>) -> Vec<String> {
+        let mut atoms = BTreeSet::new();
+        for feature in set {
+            let series = series(feature);
+            let members: Vec<_> = self
+                .features
+                .iter()
+                .filter(|f| series_of(f, series))
+                .collect();
+            let whole = members.len() > 1 && members.iter().all(|f| set.contains(*f));
+            atoms.insert(if whole { series } else { feature.as_str() });
+        }
+        atoms.into_iter().map(String::from).collect()
+    }
+}
+
+impl Cfg {
+    /// Renders the `#[cfg(...)]` attribute, indented by `tab`.
+    ///
+    /// Long lists are split over several lines, with the features of one
+    /// series on the same line.
+    pub fn attribute(&self, tab: &str) -> String {
+        let (features, negated) = match self {
+            Self::Any(features) => (features, false),
+            Self::NotAny(features) => (features, true),
+        };
+        let predicate = if features.len() == 1 {
+            format!(r#"feature = "{}""#, features[0])
+        } else if features.len() < 4 {
+            let list: Vec<_> = features
+                .iter()
+                .map(|f| format!(r#"feature = "{f}""#))
+                .collect();
+            format!("any({})", list.join(", "))
+        } else {
+            let mut lines = Vec::<(&str, Vec<String>)>::new();
+            for f in features {
+                let atom = format!(r#"feature = "{f}""#);
+                match lines.last_mut() {
+                    Some((s, atoms)) if *s == series(f) => atoms.push(atom),
+                    _ => lines.push((series(f), vec![atom])),
+                }
+            }
+            let lines: Vec<_> = lines.iter().map(|(_, atoms)| atoms.join(", ")).collect();
+            format!(
+                "any(\n    {tab}{}\n{tab})",
+                lines.join(&format!(",\n    {tab}"))
+            )
+        };
+        if negated {
+            format!("{tab}#[cfg(not({predicate}))]\n")
+        } else {
+            format!("{tab}#[cfg({predicate})]\n")
+        }
+    }
+}
+
+impl ToTokens for Cfg {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let (features, negated) = match self {
+            Self::Any(features) => (features, false),
+            Self::NotAny(features) => (features, true),
+        };
+        let mut predicate = match features.as_slice() {
+            [feature] => quote!(feature = #feature),
+            _ => quote!(any(#(feature = #features),*)),
+        };
+        if negated {
+            predicate = quote!(not(#predicate));
+        }
+        tokens.extend(quote!(#[cfg(#predicate)]));
+    }
+}
+
+/// Returns the series of a chip feature, e.g. `stm32f303` for `stm32f303xc`.
+fn series(feature: &str) -> &str {
+    static CHIP: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(stm32\w+?)x\w+$").unwrap());
+    CHIP.captures(feature)
+        .map_or(feature, |c| c.get(1).unwrap().as_str())
+}
+
+/// Returns whether `feature` is a chip feature of `series`.
+fn series_of(feature: &str, series: &str) -> bool {
+    feature != series && self::series(feature) == series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn all_features() {
+        let universe = Universe::new(["gpio-f302", "gpio-f303", "gpio-f373"]).complete(true);
+        let selected = features(&["gpio-f373", "gpio-f302", "gpio-f303"]);
+        assert_eq!(universe.cfg(&selected), None);
+    }
+
+    #[test]
+    fn single_feature() {
+        let universe = Universe::new(["gpio-f302", "gpio-f303", "gpio-f373"]).complete(true);
+        let selected = features(&["gpio-f303"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&["gpio-f303"])))
+        );
+    }
+
+    #[test]
+    fn complement() {
+        let universe =
+            Universe::new(["gpio-f302", "gpio-f303", "gpio-f373", "gpio-f334"]).complete(true);
+        let selected = features(&["gpio-f302", "gpio-f303", "gpio-f334"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::NotAny(features(&["gpio-f373"])))
+        );
+    }
+
+    #[test]
+    fn unknown_features_are_listed() {
+        let universe = Universe::new(["gpio-f302", "gpio-f303", "gpio-f373"]).complete(true);
+        let selected = features(&["gpio-f302", "gpio-f303", "gpio-f401"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&["gpio-f302", "gpio-f303", "gpio-f401"])))
+        );
+    }
+
+    #[test]
+    fn narrow_selection() {
+        // Only some MCUs were selected, other features may exist.
+        let universe = Universe::new(["gpio-f302", "gpio-f303", "gpio-f373", "gpio-f334"]);
+        let selected = features(&["gpio-f302", "gpio-f303", "gpio-f334"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&["gpio-f302", "gpio-f303", "gpio-f334"])))
+        );
+        let selected = features(&["gpio-f302", "gpio-f303", "gpio-f373", "gpio-f334"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&[
+                "gpio-f302",
+                "gpio-f303",
+                "gpio-f334",
+                "gpio-f373"
+            ])))
+        );
+    }
+
+    #[test]
+    fn series_features() {
+        let universe = Universe::new([
+            "stm32f302x8",
+            "stm32f303xb",
+            "stm32f303xc",
+            "stm32f373xc",
+            "stm32f373xb",
+        ])
+        .complete(true);
+        let selected = features(&["stm32f302x8", "stm32f303xb", "stm32f303xc"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::NotAny(features(&["stm32f373"])))
+        );
+        let selected = features(&["stm32f303xb", "stm32f303xc", "stm32f373xc"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&["stm32f303", "stm32f373xc"])))
+        );
+    }
+
+    #[test]
+    fn series_of_narrow_selection() {
+        let universe = Universe::new(["stm32f303xb", "stm32f303xc", "stm32f373xc"]);
+        let selected = features(&["stm32f303xb", "stm32f303xc"]);
+        assert_eq!(
+            universe.cfg(&selected),
+            Some(Cfg::Any(features(&["stm32f303xb", "stm32f303xc"])))
+        );
+    }
+
+    #[test]
+    fn attribute() {
+        let cfg = Cfg::NotAny(features(&["gpio-f373"]));
+        assert_eq!(
+            cfg.attribute("    "),
+            "    #[cfg(not(feature = \"gpio-f373\"))]\n"
+        );
+    }
+}
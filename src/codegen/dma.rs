@@ -1,78 +1,269 @@
+use crate::codegen::{cfg::Universe, dmamux, emit, ip_version_feature};
 use crate::cubemx::ip::dma;
-use anyhow::{anyhow, Result};
-use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+use anyhow::{anyhow, bail, Context, Result};
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    str::FromStr,
+};
 
 pub type Map = BTreeMap<String, BTreeMap<(CR, SC), BTreeMap<String, Mode>>>;
 
-pub fn print_table(maps: &BTreeMap<String, Map>) {
-    for (target, map) in maps {
-        println!("#[cfg(feature = \"{target}\")]");
-        println!("dma_map! {{");
+/// The tables of one DMA controller IP, by IP version feature, e.g.
+/// `dma-f417`.
+#[derive(Debug)]
+pub struct Tables {
+    /// `DMA`, `BDMA` or `MDMA`.
+    pub ip_name: String,
+    pub maps: BTreeMap<String, Map>,
+    pub requests: BTreeMap<String, Vec<dmamux::Request>>,
+}
+
+/// Collects the tables of the versions of a controller IP. The requests
+/// routed through a DMAMUX are numbered by the `headers` of the `families`
+/// using each version.
+pub fn tables(
+    ip_name: &str,
+    ips: &[dma::Ip],
+    families: &BTreeMap<String, BTreeSet<String>>,
+    headers: &dmamux::Headers,
+) -> Result<Tables> {
+    let mut tables = Tables {
+        ip_name: ip_name.to_string(),
+        maps: BTreeMap::new(),
+        requests: BTreeMap::new(),
+    };
+    let mut versions = BTreeMap::new();
+    let no_families = BTreeSet::new();
+    for ip in ips {
+        let feature = ip_version_feature(ip_name, &ip.version)?;
+        if let Some(version) = versions.insert(feature.clone(), &ip.version) {
+            bail!(
+                "{} IP versions {} and {} have the same feature {}",
+                ip_name,
+                version,
+                ip.version,
+                feature
+            );
+        }
+        tables.maps.insert(feature.clone(), ip_to_table(ip)?);
+        let families = families.get(&ip.version).unwrap_or(&no_families);
+        tables
+            .requests
+            .insert(feature, dmamux::family_requests(ip, families, headers)?);
+    }
+    Ok(tables)
+}
+
+/// One DMA mapping of a peripheral request, as emitted by `--format`.
+///
+/// Either `stream` or `channel` identifies the DMA stream or channel, either
+/// `channel_select` or `request` the value selecting the peripheral on it.
+/// Requests routed through a DMAMUX only have a `request` line, their
+/// controller is `DMAMUX`, or `BDMA` and `MDMA` on the H7.
+#[derive(Debug, Serialize)]
+pub struct MappingRecord {
+    pub feature: String,
+    pub controller: String,
+    pub stream: Option<u8>,
+    pub channel: Option<u8>,
+    pub channel_select: Option<u8>,
+    pub request: Option<u8>,
+    pub signal: String,
+    /// Supported directions, separated by `|`.
+    pub directions: String,
+}
+
+pub fn records(tables: &[Tables]) -> Vec<MappingRecord> {
+    let mut records = Vec::new();
+    for tables in tables {
+        controller_records(tables, &mut records);
+    }
+    records
+}
+
+fn controller_records(tables: &Tables, records: &mut Vec<MappingRecord>) {
+    for (target, map) in &tables.maps {
         for (dma, x) in map {
             for ((c, s), xx) in x {
+                let (stream, channel) = match s {
+                    SC::Stream(s) => (Some(*s), None),
+                    SC::Channel(c) => (None, Some(*c)),
+                };
+                let (channel_select, request) = match c {
+                    CR::Channel(c) => (Some(*c), None),
+                    CR::Request(r) => (None, Some(*r)),
+                    CR::Fixed => (None, None),
+                };
                 for (modename, mode) in xx {
-                    let dirs = mode.direction.iter().map(ToString::to_string).collect::<Vec<_>>().join(" | ");
-                    //let bb = mode.mode.join(" | ");
-                    match (s, c) {
-                        (SC::Stream(s), CR::Channel(c)) => {
-                            println!(
-                                "    (Stream{s}<{dma}>:{c}, {modename}, [{dirs}]), //{modename}"
-                            );
-                        }
-                        (SC::Channel(s), CR::Request(c)) => {
-                            println!(
-                                "    (Channel{s}<{dma}>:{c}, {modename}, [{dirs}]), //{modename}"
-                            );
-                        }
-                        _ => unreachable!(),
-                    }
+                    records.push(MappingRecord {
+                        feature: target.clone(),
+                        controller: dma.clone(),
+                        stream,
+                        channel,
+                        channel_select,
+                        request,
+                        signal: modename.clone(),
+                        directions: join_directions(&mode.direction),
+                    });
                 }
             }
         }
-        println!("}}");
     }
+    for (target, requests) in &tables.requests {
+        for request in requests {
+            records.push(MappingRecord {
+                feature: target.clone(),
+                controller: dmamux::controller(&tables.ip_name).to_string(),
+                stream: None,
+                channel: None,
+                channel_select: None,
+                request: Some(request.line),
+                signal: request.signal.clone(),
+                directions: join_directions(&request.directions),
+            });
+        }
+    }
+}
+
+fn join_directions(directions: &[Direction]) -> String {
+    directions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("|")
 }
 
-pub fn get_mode_maps(ip: &dma::Ip) -> BTreeMap<String, Mode> {
+/// Renders the tables of every controller IP, one after the other.
+pub fn gen_tables(tables: &[Tables], complete: bool) -> Result<String> {
+    let mut tokens = TokenStream::new();
+    for tables in tables {
+        tokens.extend(gen_table(&tables.ip_name, &tables.maps, complete)?);
+        tokens.extend(dmamux::render_requests(
+            &tables.ip_name,
+            &tables.requests,
+            complete,
+        )?);
+    }
+    let mut out = String::from("\n");
+    out.push_str(&emit::format_file(tokens)?);
+    Ok(out)
+}
+
+/// A `dma_map!` entry: controller, selection, stream or channel, signal and
+/// directions.
+type Entry<'a> = (&'a str, CR, SC, &'a str, &'a [Direction]);
+
+/// Renders a `dma_map!` per set of IP version features, `bdma_map!` and
+/// `mdma_map!` for the other controllers. Every entry is listed once, in the
+/// invocation of exactly the features that contain it.
+fn gen_table(ip_name: &str, maps: &BTreeMap<String, Map>, complete: bool) -> Result<TokenStream> {
+    let mut features = BTreeMap::<Entry, BTreeSet<String>>::new();
+    for (feature, map) in maps {
+        for (dma, x) in map {
+            for ((c, s), xx) in x {
+                for (modename, mode) in xx {
+                    features
+                        .entry((dma, *c, *s, modename, &mode.direction))
+                        .or_default()
+                        .insert(feature.clone());
+                }
+            }
+        }
+    }
+    let mut groups = BTreeMap::<BTreeSet<String>, Vec<Entry>>::new();
+    for (entry, features) in features {
+        groups.entry(features).or_default().push(entry);
+    }
+
+    let universe = Universe::new(maps.keys().cloned()).complete(complete);
+    let macro_name = format_ident!("{}_map", ip_name.to_lowercase());
+    let mut tokens = TokenStream::new();
+    for (features, entries) in &groups {
+        let mut items = Vec::new();
+        for (dma, c, s, modename, directions) in entries {
+            let dma = emit::ident(dma)?;
+            let dirs = directions
+                .iter()
+                .map(|d| emit::ident(&d.to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            // Some signals are named like `TIM1_CH4/TRIG/COM`.
+            let signal = TokenStream::from_str(modename)
+                .map_err(|_| anyhow!("invalid DMA signal: {}", modename))?;
+            let stream = match (s, c) {
+                (SC::Stream(s), CR::Channel(c)) => {
+                    let (s, c) = (format_ident!("Stream{}", s), Literal::u8_unsuffixed(*c));
+                    quote!(#s<#dma>:#c)
+                }
+                (SC::Channel(s), CR::Request(c)) => {
+                    let (s, c) = (format_ident!("Channel{}", s), Literal::u8_unsuffixed(*c));
+                    quote!(#s<#dma>:#c)
+                }
+                (SC::Channel(s), CR::Fixed) => {
+                    let s = format_ident!("Channel{}", s);
+                    quote!(#s<#dma>)
+                }
+                _ => bail!("unsupported DMA mapping of {} on {:?}: {}", modename, s, c),
+            };
+            items.push(quote!((#stream, #signal, [#(#dirs)|*])));
+        }
+        let cfg = universe.cfg(features);
+        tokens.extend(quote! {
+            #cfg
+            #macro_name! { #(#items,)* }
+        });
+    }
+    Ok(tokens)
+}
+
+pub fn get_mode_maps(ip: &dma::Ip) -> Result<BTreeMap<String, Mode>> {
     let mut modemap = BTreeMap::new();
     for refmode in &ip.modes {
-        if !(refmode.basemode == "DMA_Request" && refmode.name != "MEMTOMEM") {
+        if !refmode.is_request() {
             continue;
         }
         let name = refmode.name.clone();
         let mut channel = None;
         let mut request = None;
+        let mut muxed = false;
         let mut direction = Vec::new();
-        let mut mode = Vec::new();
-        let mut instance = Vec::new();
         for param in &refmode.parameters {
             match param.name.as_str() {
                 "Channel" => {
                     for val in &param.possible_values {
-                        assert!(channel.is_none());
-                        assert!(val.starts_with("DMA_CHANNEL_"));
-                        channel = Some(val[12..].parse().unwrap());
+                        if channel.is_some() {
+                            bail!("{} of {} has several channels", name, ip.version);
+                        }
+                        channel = Some(parse_number(val, "DMA_CHANNEL_").with_context(|| {
+                            format!("invalid channel {} of {} in {}", val, name, ip.version)
+                        })?);
                     }
                 }
                 "Request" => {
                     for val in &param.possible_values {
-                        assert!(request.is_none());
-                        assert!(val.starts_with("DMA_REQUEST_"));
-                        request = Some(val[12..].parse().unwrap());
+                        if dmamux::request_name(val).is_some() {
+                            muxed = true;
+                            continue;
+                        }
+                        if request.is_some() {
+                            bail!("{} of {} has several requests", name, ip.version);
+                        }
+                        request = Some(parse_number(val, "DMA_REQUEST_").with_context(|| {
+                            format!("invalid request {} of {} in {}", val, name, ip.version)
+                        })?);
                     }
                 }
                 "Direction" => {
                     for d in &param.possible_values {
-                        let d = Direction::from_str(d).unwrap();
+                        let d = Direction::from_str(d).map_err(|_| {
+                            anyhow!("invalid direction {} of {} in {}", d, name, ip.version)
+                        })?;
                         direction.push(d);
                     }
                 }
-                "Mode" => {
-                    mode.extend(param.possible_values.iter().cloned());
-                }
-                "IpInstance" => {
-                    instance.extend(param.possible_values.iter().cloned());
-                }
                 _ => {}
             }
         }
@@ -81,47 +272,52 @@ pub fn get_mode_maps(ip: &dma::Ip) -> BTreeMap<String, Mode> {
             CR::Channel(channel)
         } else if let Some(request) = request {
             CR::Request(request)
-        } else {
-            println!("Channel is absent in `{name}`");
+        } else if muxed {
+            // Routed through the DMAMUX, see `dmamux`.
             continue;
+        } else {
+            CR::Fixed
         };
-        modemap.insert(
-            name,
-            Mode {
-                cr,
-                direction,
-                mode,
-                instance,
-            },
-        );
+        modemap.insert(name, Mode { cr, direction });
     }
-    modemap
+    Ok(modemap)
 }
 
-pub fn ip_to_table(ip: &dma::Ip) -> Result<(String, Map)> {
-    let modemap = get_mode_maps(ip);
+/// Parses the number of a value like `DMA_CHANNEL_3`.
+fn parse_number(value: &str, prefix: &str) -> Option<u8> {
+    value.strip_prefix(prefix)?.parse().ok()
+}
+
+pub fn ip_to_table(ip: &dma::Ip) -> Result<Map> {
+    let modemap = get_mode_maps(ip)?;
+    let muxed = dmamux::signals(ip);
     let mut map: Map = BTreeMap::new();
     for dma in &ip.dmas {
         for mode in &dma.modes {
             let dma_name = &mode.name;
-            assert!(dma_name.starts_with("DMA"));
+            if !dma_name.contains("DMA") {
+                bail!("invalid DMA controller {} in {}", dma_name, ip.version);
+            }
             for operator in &mode.operators {
                 for stream in &operator.modes {
                     let stream_name = &stream.name;
-                    let stream_id = if stream_name.starts_with(&format!("{dma_name}_Stream")) {
-                        SC::Stream(stream_name[11..].parse()?)
-                    } else if stream_name.starts_with(&format!("{dma_name}_Channel")) {
-                        SC::Channel(stream_name[12..].parse()?)
+                    let suffix = stream_name.strip_prefix(&format!("{dma_name}_"));
+                    let stream_id = if let Some(n) = suffix.and_then(|s| s.strip_prefix("Stream")) {
+                        SC::Stream(n.parse()?)
+                    } else if let Some(n) = suffix.and_then(|s| s.strip_prefix("Channel")) {
+                        SC::Channel(n.parse()?)
                     } else {
                         return Err(anyhow!("Stream/Channel name {stream_name} is incorrect"));
                     };
                     for operator in &stream.operators {
                         for mode in &operator.modes {
                             let mode_name = &mode.name;
-                            if mode_name == "MEMTOMEM" {
+                            if mode_name == "MEMTOMEM" || muxed.contains_key(mode_name.as_str()) {
                                 continue;
                             }
-                            let m = modemap.get(mode_name).expect("Missing entry for {mode_name}");
+                            let m = modemap
+                                .get(mode_name)
+                                .with_context(|| format!("missing entry for {mode_name}"))?;
                             map.entry(dma_name.clone())
                                 .or_default()
                                 .entry((m.cr, stream_id))
@@ -133,21 +329,34 @@ pub fn ip_to_table(ip: &dma::Ip) -> Result<(String, Map)> {
             }
         }
     }
-    Ok((ip.version.clone(), map))
+    Ok(map)
 }
 
 #[derive(Clone, Debug)]
 pub struct Mode {
     pub cr: CR,
     pub direction: Vec<Direction>,
-    pub mode: Vec<String>,
-    pub instance: Vec<String>,
 }
 
+/// How a peripheral request is selected on a DMA stream or channel.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum CR {
+    /// Value of the channel select field of the stream.
     Channel(u8),
+    /// Value of the request select field of the channel.
     Request(u8),
+    /// Hardwired to the channel, as on F0/F1/F3/L1.
+    Fixed,
+}
+
+impl Display for CR {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Channel(c) => write!(f, "channel {c}"),
+            Self::Request(r) => write!(f, "request {r}"),
+            Self::Fixed => f.write_str("fixed"),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -160,16 +369,22 @@ pub enum SC {
 pub enum Direction {
     PtM,
     MtP,
-    MtM
+    MtM,
 }
 
 impl FromStr for Direction {
     type Err = ();
+    /// Parses a direction like `DMA_PERIPH_TO_MEMORY`, or its `BDMA_` and
+    /// `MDMA_` spelling.
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = ["DMA_", "BDMA_", "MDMA_"]
+            .iter()
+            .find_map(|prefix| s.strip_prefix(prefix))
+            .ok_or(())?;
         match s {
-            "DMA_PERIPH_TO_MEMORY" => Ok(Self::PtM),
-            "DMA_MEMORY_TO_PERIPH" => Ok(Self::MtP),
-            "DMA_MEMORY_TO_MEMORY" => Ok(Self::MtM),
+            "PERIPH_TO_MEMORY" => Ok(Self::PtM),
+            "MEMORY_TO_PERIPH" => Ok(Self::MtP),
+            "MEMORY_TO_MEMORY" => Ok(Self::MtM),
             _ => Err(()),
         }
     }
@@ -183,4 +398,112 @@ impl Display for Direction {
             Self::MtM => f.write_str("MemoryToMemory"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BDMA: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<IP Name="BDMA" Version="STM32H7_bdma_v1_0">
+    <RefMode BaseMode="BDMA_Request" Name="LPUART1_RX">
+        <Parameter Name="Request">
+            <PossibleValue>BDMA_REQUEST_LPUART1_RX</PossibleValue>
+        </Parameter>
+        <Parameter Name="Direction">
+            <PossibleValue>DMA_PERIPH_TO_MEMORY</PossibleValue>
+        </Parameter>
+    </RefMode>
+    <RefMode BaseMode="BDMA_Request" Name="SPI6_TX">
+        <Parameter Name="Request">
+            <PossibleValue>BDMA_REQUEST_SPI6_TX</PossibleValue>
+        </Parameter>
+        <Parameter Name="Direction">
+            <PossibleValue>BDMA_MEMORY_TO_PERIPH</PossibleValue>
+        </Parameter>
+    </RefMode>
+    <ModeLogicOperator Name="OR">
+        <Mode Name="BDMA">
+            <ModeLogicOperator Name="OR">
+                <Mode Name="BDMA_Channel0">
+                    <ModeLogicOperator Name="XOR">
+                        <Mode Name="LPUART1_RX"/>
+                        <Mode Name="SPI6_TX"/>
+                    </ModeLogicOperator>
+                </Mode>
+            </ModeLogicOperator>
+        </Mode>
+    </ModeLogicOperator>
+</IP>
+"#;
+
+    fn bdma_tables(xml: &str) -> Result<Tables> {
+        let ip: dma::Ip = serde_xml_rs::from_str(xml).unwrap();
+        let families = BTreeMap::from([(
+            "STM32H7_bdma_v1_0".to_string(),
+            BTreeSet::from(["STM32H7".to_string()]),
+        )]);
+        let lines = dmamux::RequestLines::from([
+            ("BDMA_REQUEST_LPUART1_RX".to_string(), 9),
+            ("BDMA_REQUEST_SPI6_TX".to_string(), 12),
+        ]);
+        let headers = dmamux::Headers::from([("stm32h7xx".to_string(), lines)]);
+        tables("BDMA", &[ip], &families, &headers)
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!(
+            Direction::from_str("DMA_PERIPH_TO_MEMORY"),
+            Ok(Direction::PtM)
+        );
+        assert_eq!(
+            Direction::from_str("BDMA_MEMORY_TO_PERIPH"),
+            Ok(Direction::MtP)
+        );
+        assert_eq!(
+            Direction::from_str("MDMA_MEMORY_TO_MEMORY"),
+            Ok(Direction::MtM)
+        );
+        assert_eq!(Direction::from_str("MDMA_BUFFER_TRANSFER"), Err(()));
+    }
+
+    #[test]
+    fn bdma_table() {
+        let tables = bdma_tables(BDMA).unwrap();
+        let directions: Vec<_> = tables.requests["bdma-h7"]
+            .iter()
+            .map(|r| r.directions.clone())
+            .collect();
+        assert_eq!(directions, [[Direction::PtM], [Direction::MtP]]);
+        assert_eq!(
+            gen_tables(&[tables], true).unwrap(),
+            r#"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BdmaMuxRequest {
+    Lpuart1Rx = 9,
+    Spi6Tx = 12,
+}
+
+impl BdmaMuxLine<Rx> for crate::pac::LPUART1 {
+    const REQUEST: BdmaMuxRequest = BdmaMuxRequest::Lpuart1Rx;
+}
+
+impl BdmaMuxLine<Tx> for crate::pac::SPI6 {
+    const REQUEST: BdmaMuxRequest = BdmaMuxRequest::Spi6Tx;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn unknown_direction() {
+        let xml = BDMA.replace("BDMA_MEMORY_TO_PERIPH", "BDMA_SIDEWAYS");
+        let err = bdma_tables(&xml).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid direction BDMA_SIDEWAYS of SPI6_TX in STM32H7_bdma_v1_0"
+        );
+    }
+}
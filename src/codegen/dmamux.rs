@@ -0,0 +1,486 @@
+//! Request tables of DMA controllers behind a DMAMUX.
+//!
+//! On families with a DMAMUX (G0, G4, L4+, H7, U5, ...) every request can be
+//! routed to every channel, so instead of a `dma_map!` the HAL needs the
+//! request line of each peripheral signal. The DMA IP mode files name the
+//! requests, e.g. `DMA_REQUEST_USART1_RX`, but do not number them; the
+//! numbers are taken from the `DMA_REQUEST_*` defines of the family's HAL
+//! header, e.g. `stm32g4xx_hal_dma.h`.
+//!
+//! The H7 also routes the requests of its BDMA through a second DMAMUX, and
+//! selects the trigger of every MDMA channel in the same way, from the
+//! `BDMA_REQUEST_*` and `MDMA_REQUEST_*` defines. Each controller gets its own
+//! enum and trait, see [`type_names`].
+
+use crate::codegen::{cfg::Universe, dma::Direction, emit};
+use crate::cubemx::ip::{dma, gpio};
+use anyhow::{anyhow, bail, Context, Result};
+use convert_case::{Case, Casing};
+use once_cell::sync::Lazy;
+use proc_macro2::{Literal, TokenStream};
+use quote::quote;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Request line numbers by define name, e.g. `DMA_REQUEST_USART1_RX`.
+pub type RequestLines = BTreeMap<String, u32>;
+
+/// Request lines by HAL series, see [`series`].
+pub type Headers = BTreeMap<String, RequestLines>;
+
+/// A peripheral signal routed through the DMAMUX.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    /// Signal name in the database, e.g. `USART1_RX`.
+    pub signal: String,
+    /// Request line of the DMAMUX.
+    pub line: u8,
+    pub directions: Vec<Direction>,
+}
+
+/// A HAL header numbering the requests of the families of a HAL series.
+///
+/// Given as `PATH` the series is taken from the file name, e.g. `stm32g4xx`
+/// for `stm32g4xx_hal_dma.h`, or explicitly by a family as `FAMILY=PATH`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub series: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for Header {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((family, path)) = s.split_once('=') {
+            return Ok(Self {
+                series: series(family),
+                path: path.into(),
+            });
+        }
+        let path = PathBuf::from(s);
+        let series = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('_'))
+            .map(|(series, _)| series.to_lowercase())
+            .filter(|series| series.starts_with("stm32") && series.ends_with("xx"))
+            .with_context(|| format!("cannot tell the family of {}, pass it as FAMILY={}", s, s))?;
+        Ok(Self { series, path })
+    }
+}
+
+/// Returns the HAL series of a family, e.g. `stm32l4xx` for `STM32L4+`.
+pub fn series(family: &str) -> String {
+    let family = family.trim_end_matches('+').to_lowercase();
+    if family.starts_with("stm32") {
+        format!("{}xx", family)
+    } else {
+        format!("stm32{}xx", family)
+    }
+}
+
+/// Loads the request line numbers from the `DMA_REQUEST_*`, `BDMA_REQUEST_*`
+/// and `MDMA_REQUEST_*` defines of HAL headers. The headers of a series are
+/// merged and must not define a request differently.
+pub fn load_headers(headers: &[Header]) -> Result<Headers> {
+    let mut merged = Headers::new();
+    for header in headers {
+        let lines = merged.entry(header.series.clone()).or_default();
+        for (name, line) in load_header(&header.path)? {
+            match lines.insert(name.clone(), line) {
+                Some(other) if other != line => bail!(
+                    "{} is {} in {} but {} in another {} header",
+                    name,
+                    line,
+                    header.path.display(),
+                    other,
+                    header.series
+                ),
+                _ => {}
+            }
+        }
+    }
+    Ok(merged)
+}
+
+fn load_header(path: &Path) -> Result<RequestLines> {
+    static DEFINE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"(?m)^\s*#define\s+(?P<name>[BM]?DMA_REQUEST_\w+)\s+[(\s]*(?:\(uint32_t\)\s*)?(?:0x(?P<hex>[0-9A-Fa-f]+)|(?P<dec>\d+))",
+        )
+        .unwrap()
+    });
+
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut lines = RequestLines::new();
+    for captures in DEFINE.captures_iter(&text) {
+        let line = match (captures.name("hex"), captures.name("dec")) {
+            (Some(hex), _) => u32::from_str_radix(hex.as_str(), 16)?,
+            (_, Some(dec)) => dec.as_str().parse()?,
+            _ => unreachable!(),
+        };
+        lines.insert(captures["name"].to_string(), line);
+    }
+    if lines.is_empty() {
+        bail!("no DMA_REQUEST_* defines in {}", path.display());
+    }
+    Ok(lines)
+}
+
+/// Returns the define of a request routed through a DMAMUX, e.g.
+/// `DMA_REQUEST_USART1_RX`, or `None` for the numbered requests of DMA
+/// channels with a request selection register.
+pub fn request_name(value: &str) -> Option<&str> {
+    let name = ["DMA_REQUEST_", "BDMA_REQUEST_", "MDMA_REQUEST_"]
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix))?;
+    if name.parse::<u8>().is_ok() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Returns the names of the request enum and the request line trait of a
+/// controller IP.
+pub fn type_names(ip_name: &str) -> (String, String) {
+    match ip_name {
+        "MDMA" => ("MdmaTrigger".into(), "MdmaTriggerLine".into()),
+        _ => {
+            let name = ip_name.to_case(Case::Pascal);
+            (format!("{}MuxRequest", name), format!("{}MuxLine", name))
+        }
+    }
+}
+
+/// Returns the controller noted in the records of the requests of an IP.
+pub fn controller(ip_name: &str) -> &str {
+    match ip_name {
+        "DMA" => "DMAMUX",
+        _ => ip_name,
+    }
+}
+
+/// Returns the signals of an IP that are routed through a DMAMUX, with the
+/// name of their request.
+pub fn signals(ip: &dma::Ip) -> BTreeMap<&str, &str> {
+    muxed_modes(ip)
+        .map(|(refmode, request)| (refmode.name.as_str(), request))
+        .collect()
+}
+
+fn muxed_modes(ip: &dma::Ip) -> impl Iterator<Item = (&dma::RefMode, &str)> {
+    ip.modes.iter().filter(|m| m.is_request()).filter_map(|m| {
+        let request = m
+            .parameters
+            .iter()
+            .filter(|p| p.name == "Request")
+            .flat_map(|p| &p.possible_values)
+            .find_map(|v| request_name(v))?;
+        Some((m, request))
+    })
+}
+
+/// Returns the DMAMUX requests of an IP, numbered by the headers of the
+/// families using it, see [`requests`]. All of them must agree. The requests
+/// are skipped with a warning if the header of a family is missing.
+pub fn family_requests(
+    ip: &dma::Ip,
+    families: &BTreeSet<String>,
+    headers: &Headers,
+) -> Result<Vec<Request>> {
+    if muxed_modes(ip).next().is_none() {
+        return Ok(Vec::new());
+    }
+    let mut numbered: Option<(&str, Vec<Request>)> = None;
+    for family in families {
+        let lines = match headers.get(&series(family)) {
+            Some(lines) => lines,
+            None => {
+                eprintln!(
+                    "skipping the requests of {}: the {} routes them through a DMAMUX, pass the HAL's {} {} header to number them",
+                    ip.version,
+                    family,
+                    series(family),
+                    ip.name
+                );
+                return Ok(Vec::new());
+            }
+        };
+        let requests = requests(ip, family, lines)?;
+        match &numbered {
+            Some((other, other_requests)) if *other_requests != requests => bail!(
+                "the {} and {} headers number the requests of {} differently",
+                series(other),
+                series(family),
+                ip.version
+            ),
+            Some(_) => {}
+            None => numbered = Some((family, requests)),
+        }
+    }
+    Ok(numbered.map(|(_, requests)| requests).unwrap_or_default())
+}
+
+/// Returns the DMAMUX requests of an IP in a family, ordered by request line
+/// and numbered by the `lines` of the family's HAL series.
+pub fn requests(ip: &dma::Ip, family: &str, lines: &RequestLines) -> Result<Vec<Request>> {
+    let mut requests = Vec::new();
+    for (refmode, request) in muxed_modes(ip) {
+        let line = *lines.get(request).with_context(|| {
+            format!(
+                "{} is not defined in the {} headers",
+                request,
+                series(family)
+            )
+        })?;
+        // Software requests, like `MDMA_REQUEST_SW`, have no request line.
+        let line = match u8::try_from(line) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        let directions = refmode
+            .parameters
+            .iter()
+            .filter(|p| p.name == "Direction")
+            .flat_map(|p| &p.possible_values)
+            .map(|d| {
+                Direction::from_str(d).map_err(|_| {
+                    anyhow!(
+                        "invalid direction {} of {} in {}",
+                        d,
+                        refmode.name,
+                        ip.version
+                    )
+                })
+            })
+            .collect::<Result<_>>()?;
+        requests.push(Request {
+            signal: refmode.name.clone(),
+            line,
+            directions,
+        });
+    }
+    requests.sort_by_key(|r| r.line);
+    Ok(requests)
+}
+
+/// Renders the request enum and the request line of every peripheral signal
+/// of a controller IP, from its requests by IP version feature. There is one
+/// enum for all versions, variants and implementations missing from some of
+/// them are gated by their features.
+pub fn render_requests(
+    ip_name: &str,
+    requests: &BTreeMap<String, Vec<Request>>,
+    complete: bool,
+) -> Result<TokenStream> {
+    let mut features = BTreeMap::<(u8, &str), BTreeSet<String>>::new();
+    for (feature, requests) in requests {
+        for request in requests {
+            features
+                .entry((request.line, &request.signal))
+                .or_default()
+                .insert(feature.clone());
+        }
+    }
+    if features.is_empty() {
+        return Ok(TokenStream::new());
+    }
+
+    let universe = Universe::new(requests.keys().cloned()).complete(complete);
+    let all_features: BTreeSet<String> = features.values().flatten().cloned().collect();
+    // Variants are only conditional on the features of their enum.
+    let enum_universe = Universe::new(all_features.iter().cloned()).complete(true);
+
+    let (enum_name, trait_name) = type_names(ip_name);
+    let enum_name = emit::ident(&enum_name)?;
+    let trait_name = emit::ident(&trait_name)?;
+
+    let mut variants = Vec::new();
+    for ((line, signal), features) in &features {
+        let cfg = enum_universe.cfg(features);
+        let variant = variant(signal)?;
+        let line = Literal::u8_unsuffixed(*line);
+        variants.push(quote!(#cfg #variant = #line));
+    }
+    let cfg = universe.cfg(&all_features);
+    let mut tokens = quote! {
+        #cfg
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum #enum_name {
+            #(#variants,)*
+        }
+    };
+
+    let mut impls: Vec<_> = features.iter().collect();
+    impls.sort_by_key(|((line, signal), _)| (*signal, *line));
+    for ((_, signal), features) in impls {
+        let variant = variant(signal)?;
+        // Signals without a function, like `ADC1`, use the `Request` marker so
+        // that every implementation has the same shape.
+        let (peripheral, function) = gpio::split_signal(signal).unwrap_or((signal, "Request"));
+        let peripheral = emit::ident(peripheral)?;
+        let function = emit::ident(&type_name(function))?;
+        let cfg = universe.cfg(features);
+        tokens.extend(quote! {
+            #cfg
+            impl #trait_name<#function> for crate::pac::#peripheral {
+                const REQUEST: #enum_name = #enum_name::#variant;
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+fn variant(signal: &str) -> Result<syn::Ident> {
+    emit::ident(&type_name(signal))
+}
+
+/// Converts a signal or function name into a type name, e.g. `Usart1Rx` for
+/// `USART1_RX` or `Ch4TrigCom` for `CH4/TRIG/COM`.
+fn type_name(name: &str) -> String {
+    name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        .to_case(Case::Pascal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(signal: &str, line: u8, directions: &[Direction]) -> Request {
+        Request {
+            signal: signal.into(),
+            line,
+            directions: directions.to_vec(),
+        }
+    }
+
+    #[test]
+    fn header_defines() {
+        let path = std::env::temp_dir().join("codegen_dmamux_header_defines.h");
+        fs::write(
+            &path,
+            "#define DMA_REQUEST_MEM2MEM          0U\n\
+             #define DMA_REQUEST_USART1_RX       24U\n\
+             #define BDMA_REQUEST_SPI6_TX        12\n\
+             #define MDMA_REQUEST_QUADSPI_FIFO_TH ((uint32_t)0x00000016U)\n\
+             #define MDMA_REQUEST_SW              ((uint32_t)0x40000000U)\n\
+             #define DMA_PRIORITY_LOW             0x00000000U\n",
+        )
+        .unwrap();
+        let lines = load_header(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            lines,
+            RequestLines::from([
+                ("BDMA_REQUEST_SPI6_TX".to_string(), 12),
+                ("DMA_REQUEST_MEM2MEM".to_string(), 0),
+                ("DMA_REQUEST_USART1_RX".to_string(), 24),
+                ("MDMA_REQUEST_QUADSPI_FIFO_TH".to_string(), 0x16),
+                ("MDMA_REQUEST_SW".to_string(), 0x4000_0000),
+            ])
+        );
+    }
+
+    #[test]
+    fn request_names() {
+        assert_eq!(
+            request_name("DMA_REQUEST_USART1_RX"),
+            Some("DMA_REQUEST_USART1_RX")
+        );
+        assert_eq!(
+            request_name("BDMA_REQUEST_SPI6_TX"),
+            Some("BDMA_REQUEST_SPI6_TX")
+        );
+        assert_eq!(request_name("DMA_REQUEST_3"), None);
+        assert_eq!(request_name("DMA_CHANNEL_3"), None);
+    }
+
+    #[test]
+    fn controller_type_names() {
+        assert_eq!(
+            type_names("DMA"),
+            ("DmaMuxRequest".into(), "DmaMuxLine".into())
+        );
+        assert_eq!(
+            type_names("BDMA"),
+            ("BdmaMuxRequest".into(), "BdmaMuxLine".into())
+        );
+        assert_eq!(
+            type_names("MDMA"),
+            ("MdmaTrigger".into(), "MdmaTriggerLine".into())
+        );
+    }
+
+    #[test]
+    fn family_series() {
+        assert_eq!(series("STM32L4+"), "stm32l4xx");
+        assert_eq!(series("STM32G4"), "stm32g4xx");
+        assert_eq!(series("H7"), "stm32h7xx");
+    }
+
+    #[test]
+    fn header_argument() {
+        assert_eq!(
+            "path/to/stm32h7xx_hal_mdma.h".parse::<Header>().unwrap(),
+            Header {
+                series: "stm32h7xx".into(),
+                path: "path/to/stm32h7xx_hal_mdma.h".into(),
+            }
+        );
+        assert_eq!(
+            "STM32L4+=dma.h".parse::<Header>().unwrap(),
+            Header {
+                series: "stm32l4xx".into(),
+                path: "dma.h".into(),
+            }
+        );
+        assert!("dma.h".parse::<Header>().is_err());
+    }
+
+    #[test]
+    fn render() {
+        let requests = BTreeMap::from([
+            (
+                "dma-g4".to_string(),
+                vec![
+                    request("ADC1", 5, &[Direction::PtM]),
+                    request("USART1_RX", 24, &[Direction::PtM]),
+                ],
+            ),
+            (
+                "dma-g474".to_string(),
+                vec![request("USART1_RX", 24, &[Direction::PtM])],
+            ),
+        ]);
+        assert_eq!(
+            emit::format_file(render_requests("DMA", &requests, true).unwrap()).unwrap(),
+            r#"#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DmaMuxRequest {
+    #[cfg(feature = "dma-g4")]
+    Adc1 = 5,
+    Usart1Rx = 24,
+}
+
+#[cfg(feature = "dma-g4")]
+impl DmaMuxLine<Request> for crate::pac::ADC1 {
+    const REQUEST: DmaMuxRequest = DmaMuxRequest::Adc1;
+}
+
+impl DmaMuxLine<Rx> for crate::pac::USART1 {
+    const REQUEST: DmaMuxRequest = DmaMuxRequest::Usart1Rx;
+}
+"#
+        );
+    }
+}
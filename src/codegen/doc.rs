@@ -0,0 +1,207 @@
+use crate::codegen::ip_version_feature;
+use crate::cubemx::ip::gpio;
+use crate::pinout::Pinout;
+use anyhow::Result;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write};
+
+const AF_COUNT: usize = 16;
+
+/// A pins × AF0..AF15 table of one MCU or GPIO IP version.
+#[derive(Debug)]
+pub struct AfTable {
+    pub title: String,
+    /// Lines describing the table, e.g. the package.
+    pub details: Vec<String>,
+    pub rows: Vec<AfRow>,
+    /// Whether the table has a column for additional functions.
+    pub additional: bool,
+}
+
+#[derive(Debug)]
+pub struct AfRow {
+    pub pin: String,
+    /// Signals selected by AF0 to AF15.
+    pub afs: Vec<Vec<String>>,
+    pub additional: Vec<String>,
+}
+
+/// One cell entry of a table, as emitted by `--format`.
+#[derive(Debug, Serialize)]
+pub struct DocRecord {
+    pub table: String,
+    pub pin: String,
+    /// The alternate function, empty for additional functions.
+    pub af: Option<u8>,
+    pub signal: String,
+}
+
+pub fn mcu_table(pinout: &Pinout) -> AfTable {
+    let rows = pinout
+        .pins
+        .iter()
+        .map(|pin| AfRow {
+            pin: pin.name.to_string(),
+            afs: af_cells(&pin.afs),
+            additional: pin.additional.clone(),
+        })
+        .collect();
+    AfTable {
+        title: pinout.mcu.clone(),
+        details: vec![format!("Package: {}", pinout.package)],
+        rows,
+        additional: true,
+    }
+}
+
+/// Returns the table of a GPIO IP version. It lists every signal with an AF,
+/// including those without a function like `EVENTOUT`, which the GPIO
+/// mappings leave out.
+pub fn ip_table(ip: &gpio::Ip) -> Result<AfTable> {
+    let mut afs = BTreeMap::<(char, u8), Vec<(u8, String)>>::new();
+    for pin in &ip.pins {
+        // Supply pins like `PDR_ON` share the file with the GPIO pins.
+        let (port, number) = match (pin.port(), pin.number()) {
+            (Ok(port), Ok(number)) => (port, number),
+            _ => continue,
+        };
+        let pin_afs = afs.entry((port, number)).or_default();
+        pin_afs.extend(pin.pin_signals.iter().filter_map(|s| s.af().ok()));
+        pin_afs.sort_unstable();
+        pin_afs.dedup();
+    }
+    let rows = afs
+        .into_iter()
+        .map(|((port, number), afs)| AfRow {
+            pin: format!("P{}{}", port, number),
+            afs: af_cells(&afs),
+            additional: Vec::new(),
+        })
+        .collect();
+    Ok(AfTable {
+        title: ip_version_feature("GPIO", &ip.version)?,
+        details: vec![format!("GPIO IP version: {}", ip.version)],
+        rows,
+        additional: false,
+    })
+}
+
+fn af_cells(afs: &[(u8, String)]) -> Vec<Vec<String>> {
+    let mut cells = vec![Vec::new(); AF_COUNT];
+    for (af, signal) in afs {
+        if let Some(cell) = cells.get_mut(usize::from(*af)) {
+            cell.push(signal.clone());
+        }
+    }
+    cells
+}
+
+impl AfTable {
+    pub fn records(&self) -> Vec<DocRecord> {
+        let mut records = Vec::new();
+        for row in &self.rows {
+            for (af, signals) in row.afs.iter().enumerate() {
+                for signal in signals {
+                    records.push(DocRecord {
+                        table: self.title.clone(),
+                        pin: row.pin.clone(),
+                        af: Some(af as u8),
+                        signal: signal.clone(),
+                    });
+                }
+            }
+            for signal in &row.additional {
+                records.push(DocRecord {
+                    table: self.title.clone(),
+                    pin: row.pin.clone(),
+                    af: None,
+                    signal: signal.clone(),
+                });
+            }
+        }
+        records
+    }
+
+    fn header(&self) -> Vec<String> {
+        let mut header = vec!["Pin".to_string()];
+        header.extend((0..AF_COUNT).map(|af| format!("AF{}", af)));
+        if self.additional {
+            header.push("Additional".into());
+        }
+        header
+    }
+
+    fn cells<'a>(&'a self, row: &'a AfRow) -> impl Iterator<Item = &'a [String]> {
+        let additional = if self.additional {
+            Some(row.additional.as_slice())
+        } else {
+            None
+        };
+        row.afs.iter().map(Vec::as_slice).chain(additional)
+    }
+
+    pub fn markdown(&self, release: &str) -> Result<String> {
+        let mut out = String::new();
+        writeln!(out, "# {} alternate functions\n", self.title)?;
+        for line in &self.details {
+            writeln!(out, "{}  ", line)?;
+        }
+        writeln!(
+            out,
+            "Generated by codegen from STM32CubeMX DB release {}.\n",
+            release
+        )?;
+
+        let header = self.header();
+        writeln!(out, "| {} |", header.join(" | "))?;
+        writeln!(out, "|{}", "---|".repeat(header.len()))?;
+        for row in &self.rows {
+            let cells: Vec<_> = self.cells(row).map(|c| c.join("<br>")).collect();
+            writeln!(out, "| {} | {} |", row.pin, cells.join(" | "))?;
+        }
+        Ok(out)
+    }
+
+    pub fn html(&self, release: &str) -> Result<String> {
+        let title = format!("{} alternate functions", escape(&self.title));
+        let mut out = String::new();
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>{}</title>", title)?;
+        writeln!(
+            out,
+            "<style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #ccc; padding: 2px 6px; }}</style>"
+        )?;
+        writeln!(out, "</head>\n<body>\n<h1>{}</h1>", title)?;
+        for line in &self.details {
+            writeln!(out, "<p>{}</p>", escape(line))?;
+        }
+        writeln!(
+            out,
+            "<p>Generated by codegen from STM32CubeMX DB release {}.</p>",
+            escape(release)
+        )?;
+
+        writeln!(out, "<table>\n<tr>")?;
+        for column in self.header() {
+            writeln!(out, "<th>{}</th>", column)?;
+        }
+        writeln!(out, "</tr>")?;
+        for row in &self.rows {
+            writeln!(out, "<tr>\n<th>{}</th>", escape(&row.pin))?;
+            for cell in self.cells(row) {
+                let signals: Vec<_> = cell.iter().map(|s| escape(s)).collect();
+                writeln!(out, "<td>{}</td>", signals.join("<br>"))?;
+            }
+            writeln!(out, "</tr>")?;
+        }
+        writeln!(out, "</table>\n</body>\n</html>")?;
+        Ok(out)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
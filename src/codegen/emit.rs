@@ -0,0 +1,389 @@
+//! Emission of Rust source from token streams.
+//!
+//! Generators build their code with `quote!` and pass it to [`format_file`],
+//! which parses it as a Rust file, so that invalid syntax is reported here
+//! instead of when the HAL is compiled, and pretty-prints it. `prettyplease`
+//! leaves the bodies of macro invocations alone, they are printed here:
+//! bodies consisting of items are formatted like a file, other bodies, like
+//! the `pin!` and `dma_map!` tables, get one entry per line.
+
+use anyhow::{anyhow, Result};
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use quote::format_ident;
+use std::mem;
+use syn::visit_mut::VisitMut;
+
+/// Parses and pretty-prints a Rust file, with a blank line between the top
+/// level items.
+pub fn format_file(tokens: TokenStream) -> Result<String> {
+    let file: syn::File = syn::parse2(tokens).map_err(|e| invalid(&e))?;
+
+    let mut bodies = Bodies::default();
+    let mut items = Vec::new();
+    for mut item in file.items {
+        bodies.visit_item_mut(&mut item);
+        items.push(prettyplease::unparse(&syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items: vec![item],
+        }));
+    }
+    let mut text = items.join("\n");
+
+    for (i, (delimiter, body)) in bodies.0.into_iter().enumerate() {
+        let placeholder = placeholder(i).to_string();
+        let pos = text
+            .find(&placeholder)
+            .ok_or_else(|| anyhow!("macro body {} was not printed", i))?;
+        let line = text[..pos].rfind('\n').map_or(0, |n| n + 1);
+        let indent: String = text[line..pos].chars().take_while(|c| *c == ' ').collect();
+        let body = format_body(delimiter, body)?;
+        if body.is_empty() && delimiter == Delimiter::Brace {
+            // Drop the line of the placeholder.
+            text.replace_range(line..=pos + placeholder.len(), "");
+        } else {
+            text.replace_range(pos..pos + placeholder.len(), &indent_lines(&body, &indent));
+        }
+    }
+    Ok(text)
+}
+
+/// Returns an identifier, or an error if `name` is not a valid one.
+pub fn ident(name: &str) -> Result<syn::Ident> {
+    syn::parse_str(name).map_err(|_| anyhow!("invalid identifier: {:?}", name))
+}
+
+/// Checks that a generated file is valid Rust.
+pub fn check(source: &str) -> Result<()> {
+    syn::parse_file(source).map_err(|e| invalid(&e))?;
+    Ok(())
+}
+
+fn invalid(e: &syn::Error) -> anyhow::Error {
+    let start = e.span().start();
+    anyhow!(
+        "generated code is not valid Rust: {} at {}:{}",
+        e,
+        start.line,
+        start.column + 1
+    )
+}
+
+/// Replaces the bodies of macro invocations with placeholders and collects
+/// them.
+#[derive(Default)]
+struct Bodies(Vec<(Delimiter, TokenStream)>);
+
+impl VisitMut for Bodies {
+    fn visit_macro_mut(&mut self, mac: &mut syn::Macro) {
+        let delimiter = match mac.delimiter {
+            syn::MacroDelimiter::Paren(_) => Delimiter::Parenthesis,
+            syn::MacroDelimiter::Brace(_) => Delimiter::Brace,
+            syn::MacroDelimiter::Bracket(_) => Delimiter::Bracket,
+        };
+        let placeholder = placeholder(self.0.len());
+        let body = mem::replace(&mut mac.tokens, quote::quote!(#placeholder));
+        self.0.push((delimiter, body));
+    }
+}
+
+fn placeholder(i: usize) -> syn::Ident {
+    format_ident!("__codegen_macro_body_{}", i)
+}
+
+/// Indents all lines but the first, which continues the current line.
+fn indent_lines(text: &str, indent: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{indent}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_body(delimiter: Delimiter, body: TokenStream) -> Result<String> {
+    if delimiter == Delimiter::Brace {
+        if let Ok(file) = syn::parse2::<syn::File>(body.clone()) {
+            if !file.items.is_empty() {
+                let text = format_file(body)?;
+                return Ok(text.trim_end().to_string());
+            }
+        }
+    }
+    let mut printer = Printer::default();
+    printer.block(body);
+    Ok(printer.out.trim_end().to_string())
+}
+
+/// Prints macro bodies that are not Rust items.
+///
+/// Entries separated by `,` or `;` go on lines of their own, as do attributes
+/// and the elements of bracketed lists. Everything else is printed inline.
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    /// The previous token on the current line, `None` at the start of a line.
+    prev: Option<TokenTree>,
+}
+
+impl Printer {
+    fn block(&mut self, tokens: TokenStream) {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        // `,` inside of `<...>` does not separate entries.
+        let mut angle = 0usize;
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1);
+            match token {
+                TokenTree::Punct(p) if p.as_char() == '#' => {
+                    self.token(token.clone());
+                }
+                TokenTree::Group(g)
+                    if g.delimiter() == Delimiter::Bracket && is_punct(self.prev.as_ref(), '#') =>
+                {
+                    self.group_inline(g);
+                    self.newline();
+                }
+                TokenTree::Group(g) if is_list(g) => {
+                    self.space(token);
+                    let (open, close) = delimiters(g.delimiter());
+                    self.out.push(open);
+                    self.indent += 1;
+                    self.newline();
+                    self.block(g.stream());
+                    self.indent -= 1;
+                    self.newline();
+                    self.start_line();
+                    self.out.push(close);
+                    self.prev = Some(token.clone());
+                }
+                TokenTree::Punct(p) => {
+                    match p.as_char() {
+                        '<' if p.spacing() == Spacing::Alone => angle += 1,
+                        '>' if p.spacing() == Spacing::Alone => angle = angle.saturating_sub(1),
+                        _ => {}
+                    }
+                    self.token(token.clone());
+                    let separator = p.as_char() == ';' || (p.as_char() == ',' && angle == 0);
+                    let list_follows = matches!(next, Some(TokenTree::Group(g)) if is_list(g));
+                    if separator && !list_follows {
+                        self.newline();
+                    }
+                }
+                _ => self.token(token.clone()),
+            }
+        }
+    }
+
+    fn group_inline(&mut self, group: &proc_macro2::Group) {
+        self.space(&TokenTree::Group(group.clone()));
+        let (open, close) = delimiters(group.delimiter());
+        self.out.push(open);
+        self.prev = None;
+        for token in group.stream() {
+            match &token {
+                TokenTree::Group(g) => self.group_inline(g),
+                _ => self.token(token),
+            }
+        }
+        self.out.push(close);
+        self.prev = Some(TokenTree::Group(group.clone()));
+    }
+
+    fn token(&mut self, token: TokenTree) {
+        if let TokenTree::Group(g) = &token {
+            return self.group_inline(g);
+        }
+        self.space(&token);
+        self.out.push_str(&token.to_string());
+        self.prev = Some(token);
+    }
+
+    fn space(&mut self, next: &TokenTree) {
+        match &self.prev {
+            None => self.start_line(),
+            Some(prev) if needs_space(prev, next) => self.out.push(' '),
+            Some(_) => {}
+        }
+    }
+
+    fn start_line(&mut self) {
+        if self.out.ends_with('\n') {
+            self.out.push_str(&"    ".repeat(self.indent));
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        self.prev = None;
+    }
+}
+
+/// Bracketed lists and braced blocks are printed over several lines.
+fn is_list(group: &proc_macro2::Group) -> bool {
+    match group.delimiter() {
+        Delimiter::Brace => true,
+        Delimiter::Bracket => group
+            .stream()
+            .into_iter()
+            .any(|t| matches!(&t, TokenTree::Punct(p) if p.as_char() == ',' || p.as_char() == ';')),
+        _ => false,
+    }
+}
+
+fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(p)) if p.as_char() == c)
+}
+
+fn needs_space(prev: &TokenTree, next: &TokenTree) -> bool {
+    if let TokenTree::Punct(p) = prev {
+        if p.spacing() == Spacing::Joint || matches!(p.as_char(), '#' | '<' | ':' | '!' | '&' | '/')
+        {
+            return false;
+        }
+    }
+    match next {
+        TokenTree::Punct(p) => {
+            !matches!(p.as_char(), ',' | ';' | ':' | '<' | '>' | '.' | '!' | '/')
+        }
+        TokenTree::Group(g) => {
+            !(g.delimiter() == Delimiter::Parenthesis && matches!(prev, TokenTree::Ident(_)))
+        }
+        _ => true,
+    }
+}
+
+fn delimiters(delimiter: Delimiter) -> (char, char) {
+    match delimiter {
+        Delimiter::Parenthesis => ('(', ')'),
+        Delimiter::Brace => ('{', '}'),
+        Delimiter::Bracket => ('[', ']'),
+        Delimiter::None => (' ', ' '),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::quote;
+
+    #[test]
+    fn separates_items() {
+        let tokens = quote! {
+            use crate::gpio;
+            pub struct Pin;
+        };
+        assert_eq!(
+            format_file(tokens).unwrap(),
+            "use crate::gpio;\n\npub struct Pin;\n"
+        );
+    }
+
+    #[test]
+    fn prints_table_entries_on_lines() {
+        let tokens = quote! {
+            #[cfg(feature = "dma-f303")]
+            dma_map! {
+                (Channel6<DMA1>, USART2_RX, [PeripheralToMemory]),
+                (Channel7<DMA1>, USART2_TX, [MemoryToPeripheral, PeripheralToMemory]),
+            }
+        };
+        assert_eq!(
+            format_file(tokens).unwrap(),
+            "#[cfg(feature = \"dma-f303\")]\n\
+             dma_map! {\n    \
+                 (Channel6<DMA1>, USART2_RX, [PeripheralToMemory]),\n    \
+                 (Channel7<DMA1>, USART2_TX, [MemoryToPeripheral, PeripheralToMemory]),\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn prints_lists_on_lines() {
+        let tokens = quote! {
+            pin! {
+                <Tx> for [
+                    #[cfg(feature = "gpio-f303")]
+                    PA2<7>,
+                    PB3<7>,
+                ],
+            }
+        };
+        assert_eq!(
+            format_file(tokens).unwrap(),
+            "pin! {\n    \
+                 <Tx> for [\n        \
+                     #[cfg(feature = \"gpio-f303\")]\n        \
+                     PA2<7>,\n        \
+                     PB3<7>,\n    \
+                 ],\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn formats_item_bodies() {
+        let tokens = quote! {
+            pub mod gpioa {
+                items! {
+                    pub struct PA0; impl PA0 { fn new() -> Self { PA0 } }
+                }
+            }
+        };
+        assert_eq!(
+            format_file(tokens).unwrap(),
+            "pub mod gpioa {\n    \
+                 items! {\n        \
+                     pub struct PA0;\n\n        \
+                     impl PA0 {\n            \
+                         fn new() -> Self {\n                \
+                             PA0\n            \
+                         }\n        \
+                     }\n    \
+                 }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn drops_empty_bodies() {
+        let tokens = quote! {
+            pub mod gpioa {
+                pin! {}
+            }
+        };
+        assert_eq!(
+            format_file(tokens).unwrap(),
+            "pub mod gpioa {\n    pin! {\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let tokens = quote!(pub struct);
+        assert!(format_file(tokens).is_err());
+    }
+
+    #[test]
+    fn checks_source() {
+        assert!(check("pub struct Pin;\n").is_ok());
+        let e = check("pub struct Pin;\nimpl Pin {\n")
+            .unwrap_err()
+            .to_string();
+        assert!(e.starts_with("generated code is not valid Rust"));
+    }
+
+    #[test]
+    fn validates_identifiers() {
+        assert_eq!(ident("Usart1Rx").unwrap(), "Usart1Rx");
+        assert!(ident("1Rx").is_err());
+        assert!(ident("CH4/TRIG").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Data formats the generators can emit instead of source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Csv,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["json", "yaml", "csv"];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("unknown format: {}", s),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a, T> {
+    release: &'a str,
+    records: &'a [T],
+}
+
+impl Format {
+    /// Renders a list of flat records.
+    ///
+    /// JSON and YAML documents are an object with the CubeMX `release` and
+    /// the `records`, CSV files have one column per record field.
+    pub fn render<T: Serialize>(self, release: &str, records: &[T]) -> Result<String> {
+        let document = Document { release, records };
+        let mut out = match self {
+            Format::Json => serde_json::to_string_pretty(&document)?,
+            Format::Yaml => serde_yaml::to_string(&document)?,
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                for record in records {
+                    writer.serialize(record)?;
+                }
+                String::from_utf8(writer.into_inner()?)?
+            }
+        };
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
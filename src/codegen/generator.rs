@@ -0,0 +1,268 @@
+use crate::codegen::{
+    adc, dma, dmamux, doc, emit, format::Format, gen_autogen_comment, gpio, memory, rules::Rules,
+    template,
+};
+use crate::cubemx::{self, ip, package::Package, Db, Selector};
+use crate::pinout::Pinout;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// All generators, in the order `batch` runs them.
+pub static GENERATORS: &[&dyn Generator] = &[&Gpio, &Dma, &Adc, &Memory, &Doc];
+
+/// A file produced by a generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// Path relative to the output directory, e.g. `gpio.rs` or
+    /// `stm32f303xc/memory.x`.
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl Artifact {
+    pub fn new<P: Into<PathBuf>>(path: P, content: String) -> Self {
+        Self {
+            path: path.into(),
+            content,
+        }
+    }
+}
+
+/// Options shared by all generators, each one uses those that apply to it.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Write one file per HAL chip feature instead of one per MCU.
+    pub per_feature: bool,
+    /// Emit the underlying data in this format instead of source code, as a
+    /// single artifact.
+    pub format: Option<Format>,
+    /// Write documents as HTML instead of Markdown.
+    pub html: bool,
+    /// File with the GPIO pin type rules, the shipped defaults otherwise.
+    pub rules: Option<PathBuf>,
+    /// Template to render the GPIO mappings with, instead of the built-in
+    /// renderer.
+    pub template: Option<PathBuf>,
+    /// HAL DMA headers numbering the DMAMUX requests.
+    pub dma_headers: Vec<dmamux::Header>,
+}
+
+pub trait Generator: Sync {
+    /// Name of the generator, used as subcommand and region name.
+    fn name(&self) -> &'static str;
+
+    fn about(&self) -> &'static str;
+
+    /// Whether the generator writes whole files into a directory instead of
+    /// a single region of a source file.
+    fn writes_files(&self) -> bool {
+        false
+    }
+
+    /// Renders the artifacts for the selected MCUs, without autogen header.
+    ///
+    /// Generators writing a region return exactly one artifact.
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>>;
+}
+
+pub fn find(name: &str) -> Option<&'static dyn Generator> {
+    GENERATORS.iter().copied().find(|g| g.name() == name)
+}
+
+/// Returns the names of all generators.
+pub fn names() -> &'static [&'static str] {
+    static NAMES: Lazy<Vec<&str>> = Lazy::new(|| GENERATORS.iter().map(|g| g.name()).collect());
+    &NAMES
+}
+
+/// Runs a generator, checks that the Rust artifacts parse and prepends the
+/// autogen header to every source artifact. Data formats carry the CubeMX
+/// release themselves.
+pub fn run(
+    generator: &dyn Generator,
+    db: &Db,
+    selector: &Selector,
+    options: &Options,
+) -> Result<Vec<Artifact>> {
+    let package = cubemx::package::load(db)?;
+    let mut artifacts = generator.generate(db, selector, options)?;
+    for artifact in &mut artifacts {
+        if artifact.path.extension().is_some_and(|e| e == "rs") {
+            emit::check(&artifact.content)
+                .with_context(|| format!("{} generator wrote invalid code", generator.name()))?;
+        }
+        artifact
+            .content
+            .insert_str(0, &header(&package, &artifact.path));
+    }
+    Ok(artifacts)
+}
+
+fn header(package: &Package, path: &Path) -> String {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("rs") => gen_autogen_comment(package),
+        Some("x") => format!(
+            "/* auto-generated using codegen */\n/* STM32CubeMX DB release: {} */\n",
+            package.pack_description.release
+        ),
+        _ => String::new(),
+    }
+}
+
+fn data<T: Serialize>(db: &Db, name: &str, format: Format, records: &[T]) -> Result<Vec<Artifact>> {
+    let package = cubemx::package::load(db)?;
+    let content = format.render(&package.pack_description.release, records)?;
+    let path = format!("{}.{}", name, format.extension());
+    Ok(vec![Artifact::new(path, content)])
+}
+
+pub struct Gpio;
+
+impl Generator for Gpio {
+    fn name(&self) -> &'static str {
+        "gpio"
+    }
+
+    fn about(&self) -> &'static str {
+        "Generate GPIO mappings from an STM32CubeMX database"
+    }
+
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>> {
+        let gpio_ips = cubemx::load_gpio_ips(db, selector)?;
+        let mappings = gpio::Mappings::new(&gpio_ips)?;
+        if let Some(format) = options.format {
+            return data(db, self.name(), format, &mappings.records());
+        }
+        let rules = Rules::load(options.rules.as_deref())?;
+        let content = match &options.template {
+            Some(path) => template::render_file(path, &mappings, &rules, selector.is_complete())?,
+            None => gpio::gen_mappings(&mappings, &rules, selector.is_complete())?,
+        };
+        Ok(vec![Artifact::new("gpio.rs", content)])
+    }
+}
+
+pub struct Dma;
+
+impl Generator for Dma {
+    fn name(&self) -> &'static str {
+        "dma"
+    }
+
+    fn about(&self) -> &'static str {
+        "Generate DMA tables and DMAMUX request lines"
+    }
+
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>> {
+        let headers = dmamux::load_headers(&options.dma_headers)?;
+        let mcus = cubemx::load_mcus(db, selector)?;
+        let families = cubemx::version_families(&mcus);
+        let mut tables = Vec::new();
+        for ip_name in ip::dma::NAMES {
+            let ips = cubemx::load_dma_ips(db, ip_name, &mcus)?;
+            if !ips.is_empty() {
+                tables.push(dma::tables(ip_name, &ips, &families, &headers)?);
+            }
+        }
+        if let Some(format) = options.format {
+            return data(db, self.name(), format, &dma::records(&tables));
+        }
+        let content = dma::gen_tables(&tables, selector.is_complete())?;
+        Ok(vec![Artifact::new("dma.rs", content)])
+    }
+}
+
+pub struct Adc;
+
+impl Generator for Adc {
+    fn name(&self) -> &'static str {
+        "adc"
+    }
+
+    fn about(&self) -> &'static str {
+        "Generate ADC channel tables from an STM32CubeMX database"
+    }
+
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>> {
+        let mcus = cubemx::load_mcus(db, selector)?;
+        if let Some(format) = options.format {
+            return data(db, self.name(), format, &adc::records(&mcus)?);
+        }
+        let content = adc::gen_channels(&mcus, selector.is_complete())?;
+        Ok(vec![Artifact::new("adc.rs", content)])
+    }
+}
+
+pub struct Memory;
+
+impl Generator for Memory {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn about(&self) -> &'static str {
+        "Generate memory.x linker scripts from an STM32CubeMX database"
+    }
+
+    fn writes_files(&self) -> bool {
+        true
+    }
+
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>> {
+        let mcus = cubemx::load_mcus(db, selector)?;
+        if let Some(format) = options.format {
+            return data(db, self.name(), format, &memory::records(&mcus)?);
+        }
+        Ok(memory::gen_memory_files(&mcus, options.per_feature)?
+            .into_iter()
+            .map(|(path, content)| Artifact::new(path, content))
+            .collect())
+    }
+}
+
+pub struct Doc;
+
+impl Generator for Doc {
+    fn name(&self) -> &'static str {
+        "doc"
+    }
+
+    fn about(&self) -> &'static str {
+        "Generate alternate function tables per MCU and GPIO IP version for the documentation"
+    }
+
+    fn writes_files(&self) -> bool {
+        true
+    }
+
+    fn generate(&self, db: &Db, selector: &Selector, options: &Options) -> Result<Vec<Artifact>> {
+        let mut tables = Vec::new();
+        for mcu in cubemx::load_mcus(db, selector)? {
+            let table = doc::mcu_table(&Pinout::from_mcu(db, mcu)?);
+            tables.push(("mcu", table));
+        }
+        for ip in cubemx::load_gpio_ips(db, selector)? {
+            tables.push(("gpio", doc::ip_table(&ip)?));
+        }
+
+        if let Some(format) = options.format {
+            let records: Vec<_> = tables.iter().flat_map(|(_, t)| t.records()).collect();
+            return data(db, self.name(), format, &records);
+        }
+
+        let release = cubemx::package::load(db)?.pack_description.release;
+        let mut artifacts = Vec::new();
+        for (dir, table) in &tables {
+            let (extension, content) = if options.html {
+                ("html", table.html(&release)?)
+            } else {
+                ("md", table.markdown(&release)?)
+            };
+            let path = Path::new(dir).join(format!("{}.{}", table.title, extension));
+            artifacts.push(Artifact::new(path, content));
+        }
+        Ok(artifacts)
+    }
+}
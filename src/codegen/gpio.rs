@@ -1,16 +1,30 @@
+use crate::codegen::{
+    cfg::Universe,
+    emit, ip_version_feature,
+    rules::{Otype, Rules},
+};
 use crate::cubemx::ip::gpio;
-use anyhow::{Context, Result};
-use once_cell::sync::Lazy;
-use regex::Regex;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
-
-struct Port<'a> {
-    id: char,
-    pins: Vec<&'a gpio::Pin>,
+use anyhow::{bail, Result};
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A pin in one of its alternate function modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Pin {
+    pub port: char,
+    pub number: u8,
+    pub af: u8,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Pin(char, u8, u8);
+impl ToTokens for Pin {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let name = format_ident!("P{}{}", self.port, self.number);
+        let af = Literal::u8_unsuffixed(self.af);
+        tokens.extend(quote!(#name<#af>));
+    }
+}
 
 pub type XMap = BTreeMap<
     String, // peripheral
@@ -23,459 +37,451 @@ pub type XMap = BTreeMap<
     >,
 >;
 
-#[derive(Clone, Copy)]
-enum Otype {
-    Fixed(&'static str),
-    Default(&'static str),
+/// The alternate functions of a set of GPIO IP versions.
+///
+/// It is computed once from the IP data by [`Mappings::new`] and consumed by
+/// the renderers.
+#[derive(Debug, Default)]
+pub struct Mappings {
+    /// One entry per IP version.
+    pub chips: Vec<Chip>,
+    /// The alternate functions of all chips by peripheral and function, with
+    /// the features of the chips providing them.
+    pub peripherals: XMap,
 }
 
-pub fn gen_mappings(gpio_ips: &[gpio::Ip]) -> Result<()> {
-    let mut all_macros = Vec::<PortMacro>::new();
-    let mut map = BTreeMap::new();
-    for ip in gpio_ips.iter() {
-        println!();
-        let ms = gen_gpio_ip(&mut map, ip)?;
-        for m in ms.into_iter() {
-            let mut same = None;
-            for (i, am) in all_macros.iter().enumerate() {
-                if m.string == am.string {
-                    same = Some(i);
-                    break;
-                }
+/// The GPIO ports of one IP version.
+#[derive(Debug, Serialize)]
+pub struct Chip {
+    /// IP version, e.g. `STM32F303_gpio_v1_0`.
+    pub version: String,
+    /// Feature selecting the IP version, e.g. `gpio-f303`.
+    pub feature: String,
+    pub ports: Vec<Port>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Port {
+    pub id: char,
+    pub pins: Vec<PortPin>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortPin {
+    pub number: u8,
+    /// Whether the pin starts in its debug function after reset.
+    pub debug_at_reset: bool,
+    pub afs: Vec<Af>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Af {
+    pub af: u8,
+    /// Peripheral module name, e.g. `usart2`.
+    pub peripheral: String,
+    /// Function type name, e.g. `Tx`.
+    pub function: String,
+    /// Signal name in the database, e.g. `USART2_TX`.
+    pub signal: String,
+}
+
+/// One alternate function of a pin in one GPIO IP version, as emitted by
+/// `--format`.
+#[derive(Debug, Serialize)]
+pub struct AfRecord {
+    pub feature: String,
+    pub pin: String,
+    pub af: u8,
+    pub signal: String,
+    pub peripheral: String,
+    pub function: String,
+}
+
+/// An alternate function of a pin, ordered by peripheral and function.
+type Entry<'a> = (&'a str, &'a str, Pin);
+
+impl Mappings {
+    pub fn new(gpio_ips: &[gpio::Ip]) -> Result<Self> {
+        let mut mappings = Self::default();
+        for ip in gpio_ips {
+            let feature = ip_version_feature("GPIO", &ip.version)?;
+            if let Some(chip) = mappings.chips.iter().find(|c| c.feature == feature) {
+                bail!(
+                    "GPIO IP versions {} and {} have the same feature {}",
+                    chip.version,
+                    ip.version,
+                    feature
+                );
             }
-            if let Some(i) = same {
-                all_macros[i].features.extend(m.features);
-            } else {
-                all_macros.push(m);
+            let chip = Chip {
+                version: ip.version.clone(),
+                feature,
+                ports: ip_ports(ip)?,
+            };
+            for (per, function, pin) in chip.ports.iter().flat_map(Port::entries) {
+                mappings
+                    .peripherals
+                    .entry(per.into())
+                    .or_default()
+                    .entry(function.into())
+                    .or_default()
+                    .entry(pin)
+                    .or_default()
+                    .insert(chip.feature.clone());
             }
+            mappings.chips.push(chip);
         }
+        Ok(mappings)
     }
-    let mut allmacros = Vec::<PortMacro>::new();
-    for m in all_macros.into_iter() {
-        let mut same = None;
-        for (i, am) in allmacros.iter().enumerate() {
-            if m.features == am.features {
-                same = Some(i);
-                break;
+}
+
+impl Mappings {
+    pub fn records(&self) -> Vec<AfRecord> {
+        let mut records = Vec::new();
+        for chip in &self.chips {
+            for port in &chip.ports {
+                for pin in &port.pins {
+                    for af in &pin.afs {
+                        records.push(AfRecord {
+                            feature: chip.feature.clone(),
+                            pin: format!("P{}{}", port.id, pin.number),
+                            af: af.af,
+                            signal: af.signal.clone(),
+                            peripheral: af.peripheral.clone(),
+                            function: af.function.clone(),
+                        });
+                    }
+                }
             }
         }
-        if let Some(i) = same {
-            allmacros[i].string.push('\n');
-            allmacros[i].string.push_str(&m.string);
-        } else {
-            allmacros.push(m);
-        }
+        records
     }
-    for m in allmacros {
-        println!("{m}");
+}
+
+impl Port {
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'_>> {
+        self.pins.iter().flat_map(move |pin| {
+            pin.afs.iter().map(move |af| {
+                let p = Pin {
+                    port: self.id,
+                    number: pin.number,
+                    af: af.af,
+                };
+                (af.peripheral.as_str(), af.function.as_str(), p)
+            })
+        })
     }
+}
 
-    let mut series: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+/// `complete` tells whether `mappings` cover the whole families, see
+/// [`Universe`].
+pub fn gen_mappings(mappings: &Mappings, rules: &Rules, complete: bool) -> Result<String> {
+    let mut tokens = render_channel_impls(mappings, complete)?;
+    tokens.extend(render_pin_modules(mappings, rules, complete)?);
+    let mut out = String::from("\n");
+    out.push_str(&emit::format_file(tokens)?);
+    out.push('\n');
+    out.push_str(&render_features(mappings));
+    Ok(out)
+}
 
-    let mut results = String::new();
-    for (per, x) in &map {
-        let mut all_features = BTreeSet::<String>::new();
-        for (_, xx) in x {
-            for (_, fs) in xx {
-                all_features.extend(fs.iter().cloned());
-                for s in fs {
-                    series.entry(s.into()).or_default().insert(per.into());
-                }
+/// Renders one `channel_impl!` invocation per set of features, containing the
+/// alternate functions provided by exactly these features.
+///
+/// Every alternate function is listed once, in the invocation of the features
+/// sharing it, as in the output of the original generator, which merged the
+/// `pin!` lines of all ports of all IP versions before grouping them by their
+/// features. Grouping whole ports instead would repeat the functions a port
+/// has in common with the other IP versions.
+pub fn render_channel_impls(mappings: &Mappings, complete: bool) -> Result<TokenStream> {
+    let mut groups = BTreeMap::<&BTreeSet<String>, Vec<Entry>>::new();
+    for (per, functions) in &mappings.peripherals {
+        for (function, pins) in functions {
+            for (pin, features) in pins {
+                groups
+                    .entry(features)
+                    .or_default()
+                    .push((per, function, *pin));
             }
         }
-        results.push_str(&print_features(
-            &all_features.iter().collect::<Vec<_>>(),
-            "",
-        )?);
-        results.push_str(&format!(
-            r#"pub mod {per} {{
-    use super::*;
+    }
 
-    pin! {{
-"#
-        ));
-        for (alt, xx) in x {
-            let mut otype = None;
-            if per.starts_with("uart") || per.starts_with("usart") {
-                if alt == "Tx" || alt == "Rx" {
-                    otype = Some(Otype::Default("PushPull"));
-                } else {
-                    otype = Some(Otype::Fixed("PushPull"));
-                }
-            } else if per.starts_with("tim") {
-                if alt.starts_with("Ch") {
-                    otype = Some(Otype::Default("PushPull"));
-                } else {
-                    otype = Some(Otype::Fixed("PushPull"));
-                }
-            } else if per == "ir" {
-                if alt == "Out" {
-                    otype = Some(Otype::Default("PushPull"));
-                }
-            } else if per.starts_with("lptim") {
-                if alt == "Out" {
-                    otype = Some(Otype::Default("PushPull"));
-                } else {
-                    otype = Some(Otype::Fixed("PushPull"));
-                }
-            } else if per.starts_with("comp") {
-                if alt == "Out" {
-                    otype = Some(Otype::Fixed("PushPull"));
-                }
-            } else if per.starts_with("i2c") || per.starts_with("fmpi2c") {
-                otype = Some(Otype::Fixed("OpenDrain"));
-            } else if per.starts_with("can")
-                || per.starts_with("fdcan")
-                || per.starts_with("dcmi")
-                || per.starts_with("dfsdm")
-                || per.starts_with("dsihost")
-                || per.starts_with("eth")
-                || per.starts_with("fmc")
-                || per.starts_with("fsmc")
-                || per.starts_with("i2s")
-                || per.starts_with("ltdc")
-                || per.starts_with("mdios")
-                || per.starts_with("quadspi")
-                || per.starts_with("octospi")
-                || per.starts_with("pssi")
-                || per.starts_with("rcc")
-                || per.starts_with("rtc")
-                || per.starts_with("sai")
-                || per.starts_with("sdio")
-                || per.starts_with("spi")
-                || per.starts_with("swpmi")
-                || per.starts_with("sys")
-                || per.starts_with("sdmmc")
-                || per.starts_with("spdifrx")
-                || per.starts_with("tsc")
-                || per.starts_with("usb")
-            {
-                otype = Some(Otype::Fixed("PushPull"));
+    let universe =
+        Universe::new(mappings.chips.iter().map(|c| c.feature.clone())).complete(complete);
+    let mut tokens = TokenStream::new();
+    for (features, entries) in &groups {
+        let mut modules = BTreeMap::<&str, BTreeMap<&str, Vec<Pin>>>::new();
+        for (per, function, pin) in entries {
+            modules
+                .entry(per)
+                .or_default()
+                .entry(function)
+                .or_default()
+                .push(*pin);
+        }
+
+        let mut items = Vec::new();
+        for (per, functions) in &modules {
+            let per = emit::ident(per)?;
+            let mut types = Vec::new();
+            for (function, pins) in functions {
+                let function = emit::ident(function)?;
+                types.push(quote!(<#function> for [#(#pins,)*]));
             }
-            let mut nopin = false;
-            if per.starts_with("uart") || per.starts_with("usart") || per.starts_with("can") {
-                if alt == "Tx" || alt == "Rx" {
-                    nopin = true;
+            items.push(quote! {
+                pub mod #per {
+                    use super::*;
+                    pin! { #(#types,)* }
                 }
-            } else if per.starts_with("spi") {
-                if alt == "Miso" || alt == "Mosi" || alt == "Sck" {
-                    nopin = true;
+            });
+        }
+        let cfg = universe.cfg(*features);
+        tokens.extend(quote! {
+            #cfg
+            channel_impl! { #(#items)* }
+        });
+    }
+    Ok(tokens)
+}
+
+/// Renders a module per peripheral with its `pin!` types and the HAL traits
+/// they implement. The output types and `NoPin` support of the pin types are
+/// taken from `rules`.
+pub fn render_pin_modules(
+    mappings: &Mappings,
+    rules: &Rules,
+    complete: bool,
+) -> Result<TokenStream> {
+    let universe =
+        Universe::new(mappings.chips.iter().map(|c| c.feature.clone())).complete(complete);
+    let mut tokens = TokenStream::new();
+    for (per, x) in &mappings.peripherals {
+        let all_features: BTreeSet<String> = x
+            .values()
+            .flat_map(|xx| xx.values().flatten().cloned())
+            .collect();
+        // Pins are only conditional on the features of their module.
+        let module_universe = Universe::new(all_features.iter().cloned()).complete(true);
+
+        let mut types = Vec::new();
+        for (alt, xx) in x {
+            let name = emit::ident(alt)?;
+            let (fixed, default) = match rules.otype(per, alt) {
+                Some(Otype::Fixed(otype)) => {
+                    let otype = emit::ident(otype)?;
+                    (Some(quote!(, #otype)), None)
                 }
-            } else if per.starts_with("i2s") {
-                if alt == "Mck" {
-                    nopin = true;
+                Some(Otype::Default(otype)) => {
+                    let otype = emit::ident(otype)?;
+                    (None, Some(quote!(default:#otype)))
                 }
-            }
-            let fixed = if let Some(Otype::Fixed(otype)) = otype {
-                format!(", {otype}")
-            } else {
-                String::new()
-            };
-            let default = if let Some(Otype::Default(otype)) = otype {
-                format!(" default:{otype}")
-            } else {
-                String::new()
+                None => (None, None),
             };
-            let nopin = if nopin {
-                format!("no:NoPin, ")
-            } else {
-                String::new()
-            };
-            results.push_str(&format!(
-                "        <{alt}{}>{} for {}[\n",
-                fixed, default, nopin
-            ));
-            for (pin, features) in xx {
-                let mut diff = all_features.difference(&features);
-                if !(pin.0 == 'I' && pin.1 == 8) {
-                    for f in features {
-                        series
-                            .entry(f.into())
-                            .or_default()
-                            .insert(format!("gpio{}", pin.0.to_lowercase()));
-                    }
-                }
-                let features = if diff.next().is_some() {
-                    all_features.intersection(&features).collect::<Vec<_>>()
-                } else {
-                    Vec::new()
-                };
-                results.push_str(&print_features(&features, "            ")?);
-                let pin = format!("P{}{}<{}>", pin.0, pin.1, pin.2);
-                results.push_str(&format!("            {pin},\n\n"));
-            }
-            results.push_str(&format!("        ],\n\n"));
+            let nopin = rules.nopin(per, alt).then(|| quote!(no:NoPin,));
+            let pins = xx.iter().map(|(pin, features)| {
+                let cfg = module_universe.cfg(features);
+                quote!(#cfg #pin)
+            });
+            types.push(quote!(<#name #fixed> #default for #nopin [#(#pins,)*]));
         }
 
-        results.push_str("    }\n");
-        if per.starts_with("tim") {
-            results.push_str(&format!(
-                "\n    use crate::pac::{} as TIM;\n    ",
-                per.to_uppercase()
-            ));
-            for i in 1..=4 {
-                if x.contains_key(&format!("Ch{i}")) {
-                    results.push_str(&format!(
-                        r#"impl TimCPin<C{i}> for TIM {{
-        type Ch<Otype> = Ch{i}<Otype>;
-    }}
-    "#
-                    ));
-                }
-                if x.contains_key(&format!("Ch{i}N")) {
-                    results.push_str(&format!(
-                        r#"impl TimNCPin<C{i}> for TIM {{
-        type ChN<Otype> = Ch{i}N<Otype>;
-    }}
-    "#
-                    ));
-                }
+        let cfg = universe.cfg(&all_features);
+        let module = emit::ident(per)?;
+        let impls = render_trait_impls(per, x)?;
+        tokens.extend(quote! {
+            #cfg
+            pub mod #module {
+                use super::*;
+                pin! { #(#types,)* }
+                #impls
             }
-            if x.contains_key("Bkin") {
-                results.push_str(
-                    r#"impl TimBkin for TIM {
-        type Bkin = Bkin;
+        });
     }
-    "#,
-                );
+    Ok(tokens)
+}
+
+/// Renders the implementations of the HAL traits collecting the pin types of
+/// a peripheral.
+fn render_trait_impls(
+    per: &str,
+    functions: &BTreeMap<String, BTreeMap<Pin, BTreeSet<String>>>,
+) -> Result<TokenStream> {
+    let has = |function: &str| functions.contains_key(function);
+    let pac = emit::ident(&per.to_uppercase())?;
+    let mut tokens = TokenStream::new();
+
+    if per.starts_with("tim") {
+        tokens.extend(quote!(use crate::pac::#pac as TIM;));
+        for i in 1..=4u8 {
+            let c = format_ident!("C{}", i);
+            if has(&format!("Ch{i}")) {
+                let ch = format_ident!("Ch{}", i);
+                tokens.extend(quote! {
+                    impl TimCPin<#c> for TIM {
+                        type Ch<Otype> = #ch<Otype>;
+                    }
+                });
             }
-            if x.contains_key("Etr") {
-                results.push_str(
-                    r#"impl TimEtr for TIM {
-        type Etr = Etr;
-    }
-    "#,
-                );
+            if has(&format!("Ch{i}N")) {
+                let chn = format_ident!("Ch{}N", i);
+                tokens.extend(quote! {
+                    impl TimNCPin<#c> for TIM {
+                        type ChN<Otype> = #chn<Otype>;
+                    }
+                });
             }
         }
-
-        if per.starts_with("spi") {
-            results.push_str(&format!(
-                r#"    impl SpiCommon for crate::pac::{} {{
-        type Miso = Miso;
-        type Mosi = Mosi;
-        type Nss = Nss;
-        type Sck = Sck;
-    }}
-    "#,
-                per.to_uppercase()
-            ));
-        }
-
-        if per.starts_with("can") {
-            results.push_str(&format!(
-                r#"    impl CanCommon for crate::pac::{} {{
-        type Rx = Rx;
-        type Tx = Tx;
-    }}
-    "#,
-                per.to_uppercase()
-            ));
+        if has("Bkin") {
+            tokens.extend(quote! {
+                impl TimBkin for TIM {
+                    type Bkin = Bkin;
+                }
+            });
         }
-        if per.starts_with("i2c") || per.starts_with("fmpi2c") {
-            results.push_str(&format!(
-                r#"    use crate::pac::{} as I2C;
-            impl I2cCommon for I2C {{
-                type Scl = Scl;
-                type Sda = Sda;
-                type Smba = Smba;
-            }}
-    "#,
-                per.to_uppercase()
-            ));
+        if has("Etr") {
+            tokens.extend(quote! {
+                impl TimEtr for TIM {
+                    type Etr = Etr;
+                }
+            });
         }
-
-        if per.starts_with("sai") {
-            results.push_str(&format!(
-                "    use crate::pac::{} as SAI;\n",
-                per.to_uppercase()
-            ));
-            results.push_str(
-                r##"    pub struct ChannelA;
-    pub struct ChannelB;
-    impl SaiChannels for SAI {
-        type A = ChannelA;
-        type B = ChannelB;
-    }
-    impl SaiChannel for ChannelA {
-        type Fs = FsA;
-        type Mclk = MclkA;
-        type Sck = SckA;
-        type Sd = SdA;
-    }
-    impl SaiChannel for ChannelB {
-        type Fs = FsB;
-        type Mclk = MclkB;
-        type Sck = SckB;
-        type Sd = SdB;
     }
-    "##,
-            );
-        }
 
-        if per.starts_with("spdifrx") {
-            results.push_str(
-                r##"
-    use crate::pac::SPDIFRX;
-    impl SPdifIn<0> for SPDIFRX {
-        type In = In0;
-    }
-    impl SPdifIn<1> for SPDIFRX {
-        type In = In1;
-    }
-    impl SPdifIn<2> for SPDIFRX {
-        type In = In2;
-    }
-    impl SPdifIn<3> for SPDIFRX {
-        type In = In3;
+    if per.starts_with("spi") {
+        tokens.extend(quote! {
+            impl SpiCommon for crate::pac::#pac {
+                type Miso = Miso;
+                type Mosi = Mosi;
+                type Nss = Nss;
+                type Sck = Sck;
+            }
+        });
     }
-    "##,
-            );
-        }
 
-        if per.starts_with("usart") {
-            results.push_str(&format!(
-                "    use crate::pac::{} as USART;\n",
-                per.to_uppercase()
-            ));
-            results.push_str(
-                r##"    impl SerialAsync for USART {
-        type Rx<Otype> = Rx<Otype>;
-        type Tx<Otype> = Tx<Otype>;
-    }
-    "##,
-            );
-            if x.contains_key("Ck") {
-                results.push_str(
-                    r#"impl SerialSync for USART {
-            type Ck = Ck;
-        }
-    "#,
-                );
+    if per.starts_with("can") {
+        tokens.extend(quote! {
+            impl CanCommon for crate::pac::#pac {
+                type Rx = Rx;
+                type Tx = Tx;
             }
-            if x.contains_key("Cts") {
-                results.push_str(
-                    r#"impl SerialRs232 for USART {
-            type Cts = Cts;
-            type Rts = Rts;
-        }
-    "#,
-                );
-            }
-        }
+        });
+    }
 
-        if per.starts_with("uart") {
-            results.push_str(&format!(
-                "    use crate::pac::{} as UART;\n",
-                per.to_uppercase()
-            ));
-            results.push_str(
-                r##"    impl SerialAsync for UART {
-        type Rx<Otype> = Rx<Otype>;
-        type Tx<Otype> = Tx<Otype>;
+    if per.starts_with("i2c") || per.starts_with("fmpi2c") {
+        tokens.extend(quote! {
+            use crate::pac::#pac as I2C;
+            impl I2cCommon for I2C {
+                type Scl = Scl;
+                type Sda = Sda;
+                type Smba = Smba;
+            }
+        });
     }
-    "##,
-            );
-            if x.contains_key("Ck") {
-                results.push_str(
-                    r#"impl SerialSync for UART {
-            type Ck = Ck;
-        }
-    "#,
-                );
+
+    if per.starts_with("sai") {
+        tokens.extend(quote! {
+            use crate::pac::#pac as SAI;
+            pub struct ChannelA;
+            pub struct ChannelB;
+            impl SaiChannels for SAI {
+                type A = ChannelA;
+                type B = ChannelB;
             }
-            if x.contains_key("Cts") {
-                results.push_str(
-                    r#"impl SerialRs232 for UART {
-            type Cts = Cts;
-            type Rts = Rts;
-        }
-    "#,
-                );
+            impl SaiChannel for ChannelA {
+                type Fs = FsA;
+                type Mclk = MclkA;
+                type Sck = SckA;
+                type Sd = SdA;
             }
-        }
-
-        results.push_str("}\n\n");
+            impl SaiChannel for ChannelB {
+                type Fs = FsB;
+                type Mclk = MclkB;
+                type Sck = SckB;
+                type Sd = SdB;
+            }
+        });
     }
-    println!("{results}");
-    let mut results = String::new();
-    for (s, pers) in series {
-        results.push_str(&format!("{s} = [\n    "));
-        for p in pers {
-            results.push_str(&format!("\"{p}\", "));
+
+    if per.starts_with("spdifrx") {
+        tokens.extend(quote!(
+            use crate::pac::SPDIFRX;
+        ));
+        for i in 0..4u8 {
+            let input = format_ident!("In{}", i);
+            let i = Literal::u8_unsuffixed(i);
+            tokens.extend(quote! {
+                impl SPdifIn<#i> for SPDIFRX {
+                    type In = #input;
+                }
+            });
         }
-        results.push_str("\n]\n");
     }
-    println!("{results}");
-    Ok(())
-}
 
-fn print_features(features: &[&String], tab: &str) -> anyhow::Result<String> {
-    use std::fmt::Write;
-    let mut f = String::new();
-    if features.is_empty() {
-    } else if features.len() == 1 {
-        writeln!(f, r#"{tab}#[cfg(feature = "{}")]"#, features[0])?;
-    } else if features.len() < 4 {
-        writeln!(
-            f,
-            "{tab}#[cfg(any({}))]",
-            features
-                .iter()
-                .map(|s| format!(r#"feature = "{s}""#))
-                .collect::<Vec<_>>()
-                .join(", ")
-        )?;
+    let serial = if per.starts_with("usart") {
+        Some(quote!(USART))
+    } else if per.starts_with("uart") {
+        Some(quote!(UART))
     } else {
-        writeln!(
-            f,
-            "{tab}#[cfg(any(\n    {tab}{}\n{tab}))]",
-            features
-                .iter()
-                .map(|s| format!(r#"feature = "{s}""#))
-                .collect::<Vec<_>>()
-                .join(&format!(",\n    {tab}"))
-        )?;
-    }
-    Ok(f)
-}
-
-fn gen_gpio_ip(map: &mut XMap, ip: &gpio::Ip) -> Result<Vec<PortMacro>> {
-    let feature = ip_version_to_feature(&ip.version)?;
-    let ports = merge_pins_by_port(&ip.pins)?;
-
-    let mut macros = Vec::new();
-    for port in &ports {
-        for string in gen_port(&feature, map, port)?.into_iter() {
-            macros.push(PortMacro {
-                features: vec![feature.clone()],
-                string,
+        None
+    };
+    if let Some(serial) = serial {
+        tokens.extend(quote! {
+            use crate::pac::#pac as #serial;
+            impl SerialAsync for #serial {
+                type Rx<Otype> = Rx<Otype>;
+                type Tx<Otype> = Tx<Otype>;
+            }
+        });
+        if has("Ck") {
+            tokens.extend(quote! {
+                impl SerialSync for #serial {
+                    type Ck = Ck;
+                }
+            });
+        }
+        if has("Cts") {
+            tokens.extend(quote! {
+                impl SerialRs232 for #serial {
+                    type Cts = Cts;
+                    type Rts = Rts;
+                }
             });
         }
     }
-    Ok(macros)
-}
 
-fn ip_version_to_feature(ip_version: &str) -> Result<String> {
-    static VERSION: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"^STM32(?P<version>\w+)_gpio_v1_0$").unwrap());
+    Ok(tokens)
+}
 
-    let captures = VERSION
-        .captures(ip_version)
-        .with_context(|| format!("invalid GPIO IP version: {}", ip_version))?;
+/// Renders the peripherals and GPIO ports of every feature, to be copied into
+/// the feature list of the HAL's `Cargo.toml`, as a comment.
+fn render_features(mappings: &Mappings) -> String {
+    let mut series: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for (per, functions) in &mappings.peripherals {
+        for pins in functions.values() {
+            for (pin, features) in pins {
+                for f in features {
+                    let entry = series.entry(f).or_default();
+                    entry.insert(per.clone());
+                    if !(pin.port == 'I' && pin.number == 8) {
+                        entry.insert(format!("gpio{}", pin.port.to_lowercase()));
+                    }
+                }
+            }
+        }
+    }
 
-    let version = captures.name("version").unwrap().as_str();
-    let feature = format!("gpio-{}", version.to_lowercase());
-    Ok(feature)
+    let mut out = String::new();
+    for (s, pers) in series {
+        let pers: Vec<_> = pers.iter().map(|p| format!("\"{p}\",")).collect();
+        out.push_str(&format!("// {s} = [\n//     {}\n// ]\n", pers.join(" ")));
+    }
+    out
 }
 
-fn merge_pins_by_port(pins: &[gpio::Pin]) -> Result<Vec<Port>> {
-    let mut pins_by_port = HashMap::new();
-    for pin in pins.iter() {
-        pins_by_port
-            .entry(pin.port()?)
-            .and_modify(|e: &mut Vec<_>| e.push(pin))
-            .or_insert_with(|| vec![pin]);
+fn ip_ports(ip: &gpio::Ip) -> Result<Vec<Port>> {
+    let mut pins_by_port = BTreeMap::<char, Vec<&gpio::Pin>>::new();
+    for pin in ip.pins.iter() {
+        pins_by_port.entry(pin.port()?).or_default().push(pin);
     }
 
     let mut ports = Vec::new();
@@ -485,169 +491,144 @@ fn merge_pins_by_port(pins: &[gpio::Pin]) -> Result<Vec<Port>> {
         });
         pins.sort_by_key(|p| p.number().unwrap_or_default());
         pins.dedup_by_key(|p| p.number().unwrap_or_default());
-        ports.push(Port { id, pins });
-    }
-    ports.sort_by_key(|p| p.id);
 
+        let pins: Result<_> = pins.into_iter().map(port_pin).collect();
+        ports.push(Port { id, pins: pins? });
+    }
     Ok(ports)
 }
 
-pub struct PortMacro {
-    features: Vec<String>,
-    string: String,
+fn port_pin(pin: &gpio::Pin) -> Result<PortPin> {
+    let number = pin.number()?;
+    let mut afs = Vec::new();
+    for (af, func) in get_pin_af_numbers(pin)? {
+        if let Some((per, function)) = gpio::split_signal(&func) {
+            use convert_case::{Case, Casing};
+            afs.push(Af {
+                af,
+                peripheral: per.to_lowercase(),
+                function: function.to_case(Case::Pascal),
+                signal: func.clone(),
+            });
+        }
+    }
+    Ok(PortPin {
+        number,
+        debug_at_reset: gpio::is_debug_pin(pin.port()?, number),
+        afs,
+    })
 }
 
-use core::fmt;
-
-impl fmt::Display for PortMacro {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use lazy_regex::regex_captures;
-        if self.features.len() == 1 {
-            writeln!(f, r#"#[cfg(feature = "{}")]"#, self.features[0])?;
-        } else if self.features.len() < 4 {
-            writeln!(
-                f,
-                "#[cfg(any({}))]",
-                self.features
-                    .iter()
-                    .map(|s| format!(r#"feature = "{s}""#))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            )?;
-        } else {
-            writeln!(
-                f,
-                "#[cfg(any(\n    {}\n))]",
-                self.features
-                    .iter()
-                    .map(|s| format!(r#"feature = "{s}""#))
-                    .collect::<Vec<_>>()
-                    .join(",\n    ")
-            )?;
+fn get_pin_af_numbers(pin: &gpio::Pin) -> Result<Vec<(u8, String)>> {
+    let mut numbers = Vec::new();
+    for signal in &pin.pin_signals {
+        if let Ok(af) = signal.af() {
+            numbers.push(af);
         }
-        f.write_str("channel_impl! {\n")?;
-        let mut strings = self.string.split('\n').collect::<Vec<_>>();
-        strings.sort();
-        let mut block = String::new();
-
-        let mut prev_periph = String::new();
-        let mut prev_altfun = String::new();
-        let mut pins = Vec::new();
-        for s in &strings {
-            let (_whole, periph, altfun, pin) =
-                regex_captures!(r#"  (\w+):  <(\w+)> for (.+),"#, s,).unwrap();
-            if prev_periph != periph {
-                block.push_str(&dump_pins(&prev_altfun, &pins));
-                block.push_str(&format!(
-                    r##"    }}
-}}
-pub mod {} {{
+    }
+
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
-    pin! {{
-"##,
-                    periph
-                ));
-                prev_periph = periph.into();
-                prev_altfun = altfun.into();
-                pins.clear();
-                pins.push(pin.into());
-            } else if prev_altfun != altfun {
-                block.push_str(&dump_pins(&prev_altfun, &pins));
-
-                prev_altfun = altfun.into();
-                pins.clear();
-                pins.push(pin.into());
-            } else {
-                pins.push(pin.into());
+
+    /// An alternate function of port A: peripheral, function, pin number and
+    /// AF number.
+    type PortAf<'a> = (&'a str, &'a str, u8, u8);
+
+    /// Returns mappings of chips with a single port A, by feature.
+    fn mappings(chips: &[(&str, &[PortAf])]) -> Mappings {
+        let mut mappings = Mappings::default();
+        for (feature, afs) in chips {
+            let pins = afs
+                .iter()
+                .map(|&(per, function, number, af)| PortPin {
+                    number,
+                    debug_at_reset: false,
+                    afs: vec![Af {
+                        af,
+                        peripheral: per.into(),
+                        function: function.into(),
+                        signal: format!("{}_{}", per.to_uppercase(), function.to_uppercase()),
+                    }],
+                })
+                .collect();
+            let chip = Chip {
+                version: String::new(),
+                feature: feature.to_string(),
+                ports: vec![Port { id: 'A', pins }],
+            };
+            for (per, function, pin) in chip.ports.iter().flat_map(Port::entries) {
+                mappings
+                    .peripherals
+                    .entry(per.into())
+                    .or_default()
+                    .entry(function.into())
+                    .or_default()
+                    .entry(pin)
+                    .or_default()
+                    .insert(chip.feature.clone());
             }
+            mappings.chips.push(chip);
         }
-        block.push_str(&dump_pins(&prev_altfun, &pins));
-        block.push_str(
-            r##"    }
-}
-"##,
-        );
-
-        f.write_str(&block)?;
-        f.write_str("\n}\n")?;
-        Ok(())
+        mappings
     }
-}
 
-fn dump_pins(altfun: &str, pins: &[String]) -> String {
-    let mut block = String::new();
-    block.push_str(&format!("        <{altfun}> for [\n"));
-    for p in pins {
-        block.push_str(&format!("            {p},\n"));
+    #[test]
+    fn channel_impls_by_feature_set() {
+        let mappings = mappings(&[
+            (
+                "gpio-f302",
+                &[("usart2", "Tx", 2, 7), ("tim2", "Ch1", 0, 1)],
+            ),
+            (
+                "gpio-f303",
+                &[("usart2", "Tx", 2, 7), ("tim2", "Ch1", 5, 1)],
+            ),
+        ]);
+        let tokens = render_channel_impls(&mappings, true).unwrap();
+        assert_eq!(
+            emit::format_file(tokens).unwrap(),
+            r#"#[cfg(feature = "gpio-f302")]
+channel_impl! {
+    pub mod tim2 {
+        use super::*;
+        pin! {
+            <Ch1> for [
+                PA0<1>,
+            ],
+        }
     }
-    block.push_str("        ],\n");
-    block
 }
 
-fn gen_port(feature: &str, map: &mut XMap, port: &Port) -> Result<Vec<String>> {
-    let port_upper = port.id;
-    let port_lower = port.id.to_ascii_lowercase();
-    let mut strings = Vec::new();
-    for pin in &port.pins {
-        strings.extend(gen_pin(feature, map, port_upper, port_lower, pin)?);
+channel_impl! {
+    pub mod usart2 {
+        use super::*;
+        pin! {
+            <Tx> for [
+                PA2<7>,
+            ],
+        }
     }
-
-    Ok(strings)
 }
 
-fn gen_pin(
-    feature: &str,
-    map: &mut XMap,
-    port_upper: char,
-    _port_lower: char,
-    pin: &gpio::Pin,
-) -> Result<Vec<String>> {
-    let nr = pin.number()?;
-    let _reset_mode = get_pin_reset_mode(pin)?;
-    let af_numbers = get_pin_af_numbers(pin)?;
-    let mut strings = Vec::new();
-
-    for (af, func) in af_numbers.into_iter() {
-        if let Some(pos) = func.bytes().position(|b| b == b'_') {
-            use convert_case::{Case, Casing};
-            let per = func[..pos].to_lowercase();
-            let pn = (&func[pos + 1..]).to_case(Case::Pascal);
-            strings.push(format!("  {per}:  <{pn}> for P{port_upper}{nr}<{af}>,",));
-            map.entry(per)
-                .or_default()
-                .entry(pn)
-                .or_default()
-                .entry(Pin(port_upper, nr, af))
-                .or_default()
-                .insert(feature.into());
-        } else {
-            //    println!("Skipped: {port_lower} {nr} - Unsupported func {func}");
+#[cfg(feature = "gpio-f303")]
+channel_impl! {
+    pub mod tim2 {
+        use super::*;
+        pin! {
+            <Ch1> for [
+                PA5<1>,
+            ],
         }
     }
-    Ok(strings)
 }
-
-fn get_pin_reset_mode(pin: &gpio::Pin) -> Result<Option<&'static str>> {
-    // Debug pins default to their debug function (AF0), everything else
-    // defaults to floating input or analog.
-    let mode = match (pin.port()?, pin.number()?) {
-        ('A', 13) | ('A', 14) | ('A', 15) | ('B', 3) | ('B', 4) => Some("super::Debugger"),
-        _ => None,
-    };
-    Ok(mode)
-}
-
-fn get_pin_af_numbers(pin: &gpio::Pin) -> Result<Vec<(u8, String)>> {
-    let mut numbers = Vec::new();
-    for signal in &pin.pin_signals {
-        match signal.af() {
-            Ok(af) => numbers.push(af),
-            Err(_) => {}
-        }
+"#
+        );
     }
-
-    numbers.sort_unstable();
-    numbers.dedup();
-
-    Ok(numbers)
 }
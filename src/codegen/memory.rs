@@ -0,0 +1,376 @@
+use crate::codegen::{mcu_features, mcu_variants};
+use crate::cubemx::mcu;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+const FLASH_ORIGIN: u32 = 0x0800_0000;
+const RAM_ORIGIN: u32 = 0x2000_0000;
+const CCM_RAM_ORIGIN: u32 = 0x1000_0000;
+
+/// A memory block as (region, origin, size in KiB).
+type Block = (&'static str, u32, u32);
+
+/// SRAM blocks that the MCU files only report as part of the total RAM size,
+/// by MCU name prefix.
+///
+/// The remaining RAM is placed at the start of the SRAM address space.
+const RAM_SPLITS: &[(&str, &[Block])] = &[
+    ("STM32L41", &[("SRAM2", 0x1000_0000, 8)]),
+    ("STM32L42", &[("SRAM2", 0x1000_0000, 8)]),
+    ("STM32L43", &[("SRAM2", 0x1000_0000, 16)]),
+    ("STM32L44", &[("SRAM2", 0x1000_0000, 16)]),
+    ("STM32L45", &[("SRAM2", 0x1000_0000, 32)]),
+    ("STM32L46", &[("SRAM2", 0x1000_0000, 32)]),
+    ("STM32L47", &[("SRAM2", 0x1000_0000, 32)]),
+    ("STM32L48", &[("SRAM2", 0x1000_0000, 32)]),
+    ("STM32L49", &[("SRAM2", 0x1000_0000, 64)]),
+    ("STM32L4A", &[("SRAM2", 0x1000_0000, 64)]),
+    (
+        "STM32G431",
+        &[("SRAM2", 0x2000_4000, 6), ("CCMRAM", 0x1000_0000, 10)],
+    ),
+    (
+        "STM32G441",
+        &[("SRAM2", 0x2000_4000, 6), ("CCMRAM", 0x1000_0000, 10)],
+    ),
+    (
+        "STM32G47",
+        &[("SRAM2", 0x2001_4000, 16), ("CCMRAM", 0x1000_0000, 32)],
+    ),
+    (
+        "STM32G48",
+        &[("SRAM2", 0x2001_4000, 16), ("CCMRAM", 0x1000_0000, 32)],
+    ),
+    (
+        "STM32G49",
+        &[("SRAM2", 0x2001_4000, 16), ("CCMRAM", 0x1000_0000, 16)],
+    ),
+    (
+        "STM32G4A",
+        &[("SRAM2", 0x2001_4000, 16), ("CCMRAM", 0x1000_0000, 16)],
+    ),
+];
+
+/// MCUs whose RAM is not contiguous at the start of the SRAM address space,
+/// but that the MCU files only report with their total RAM size, by MCU name
+/// prefix. Their layout can't be derived, so they are skipped.
+const UNKNOWN_RAM_LAYOUTS: &[&str] = &["STM32H7", "STM32MP1", "STM32U5"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub origin: u32,
+    /// Size in KiB.
+    pub length: u32,
+}
+
+/// Memory layout of a single chip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub name: String,
+    pub feature: String,
+    pub regions: Vec<Region>,
+}
+
+/// One memory region of a chip, as emitted by `--format`.
+#[derive(Debug, Serialize)]
+pub struct RegionRecord {
+    pub mcu: String,
+    pub feature: String,
+    pub region: String,
+    pub origin: u32,
+    /// Size in KiB.
+    pub length: u32,
+}
+
+pub fn records(mcus: &[mcu::Mcu]) -> Result<Vec<RegionRecord>> {
+    let mut records = Vec::new();
+    for mcu in mcus {
+        for memory in mcu_memories(mcu)? {
+            for region in &memory.regions {
+                records.push(RegionRecord {
+                    mcu: memory.name.clone(),
+                    feature: memory.feature.clone(),
+                    region: region.name.clone(),
+                    origin: region.origin,
+                    length: region.length,
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Generates the linker scripts for `mcus`, keyed by their path relative to
+/// the output directory. Skipped MCUs are listed in `skipped.txt`, by family.
+pub fn gen_memory_files(mcus: &[mcu::Mcu], per_feature: bool) -> Result<BTreeMap<PathBuf, String>> {
+    let mut memories = BTreeMap::<String, Memory>::new();
+    let mut skipped = String::new();
+    for mcu in mcus {
+        if let Some(reason) = skip_reason(mcu) {
+            eprintln!("skipping {}: {}", mcu.ref_name, reason);
+            skipped.push_str(&format!("{}: {}: {}\n", mcu.family, mcu.ref_name, reason));
+            continue;
+        }
+        for memory in mcu_memories(mcu)? {
+            let key = if per_feature {
+                memory.feature.clone()
+            } else {
+                memory.name.to_lowercase()
+            };
+            // Chips sharing a feature get the smallest layout of all of them.
+            memories
+                .entry(key)
+                .and_modify(|m| {
+                    if total(&memory) < total(m) {
+                        *m = memory.clone();
+                    }
+                })
+                .or_insert(memory);
+        }
+    }
+
+    let mut files: BTreeMap<PathBuf, String> = memories
+        .iter()
+        .map(|(key, memory)| {
+            let path = [key.as_str(), "memory.x"].iter().collect();
+            (path, gen_memory_x(memory))
+        })
+        .collect();
+    if !skipped.is_empty() {
+        files.insert("skipped.txt".into(), skipped);
+    }
+    Ok(files)
+}
+
+fn total(memory: &Memory) -> u32 {
+    memory.regions.iter().map(|r| r.length).sum()
+}
+
+pub fn gen_memory_x(memory: &Memory) -> String {
+    let mut s = String::new();
+    s.push_str(&format!("/* {} */\n", memory.name));
+    s.push_str("MEMORY\n{\n");
+    for region in &memory.regions {
+        s.push_str(&format!(
+            "  {} : ORIGIN = 0x{:08X}, LENGTH = {}K\n",
+            region.name, region.origin, region.length
+        ));
+    }
+    s.push_str("}\n");
+    s
+}
+
+/// Returns why no memory layout can be derived for an MCU, if it is skipped.
+pub fn skip_reason(mcu: &mcu::Mcu) -> Option<String> {
+    if let Err(e) = mcu_features(&mcu.ref_name) {
+        return Some(format!("{:#}", e));
+    }
+    if UNKNOWN_RAM_LAYOUTS
+        .iter()
+        .any(|prefix| mcu.ref_name.starts_with(prefix))
+    {
+        return Some("unknown RAM layout".into());
+    }
+    None
+}
+
+/// Returns the memory layouts of all chips covered by an MCU file, none if it
+/// is skipped, see [`skip_reason`].
+///
+/// The file lists flash and RAM sizes in the order of the variants in its
+/// reference name, a single RAM size applies to all of them.
+pub fn mcu_memories(mcu: &mcu::Mcu) -> Result<Vec<Memory>> {
+    if let Some(reason) = skip_reason(mcu) {
+        eprintln!("skipping {}: {}", mcu.ref_name, reason);
+        return Ok(Vec::new());
+    }
+    let variants = mcu_variants(&mcu.ref_name);
+    let features = mcu_features(&mcu.ref_name)?;
+
+    let mut memories = Vec::new();
+    for (i, (name, feature)) in variants.into_iter().zip(features).enumerate() {
+        let pick = |sizes: &[u32]| sizes.get(i).or_else(|| sizes.last()).copied();
+        let flash =
+            pick(&mcu.flash).with_context(|| format!("missing flash size of {}", mcu.ref_name))?;
+        let ram =
+            pick(&mcu.ram).with_context(|| format!("missing RAM size of {}", mcu.ref_name))?;
+
+        let mut regions = vec![Region {
+            name: "FLASH".into(),
+            origin: FLASH_ORIGIN,
+            length: flash,
+        }];
+        let mut main_ram = ram;
+        let mut extra = Vec::new();
+        if let Some((_, blocks)) = RAM_SPLITS.iter().find(|(p, _)| name.starts_with(p)) {
+            for &(block, origin, length) in blocks.iter() {
+                main_ram = main_ram
+                    .checked_sub(length)
+                    .with_context(|| format!("RAM of {} is smaller than its {}", name, block))?;
+                extra.push(Region {
+                    name: block.into(),
+                    origin,
+                    length,
+                });
+            }
+        } else if let Some(ccm_ram) = pick(&mcu.ccm_ram) {
+            extra.push(Region {
+                name: "CCMRAM".into(),
+                origin: CCM_RAM_ORIGIN,
+                length: ccm_ram,
+            });
+        }
+        regions.push(Region {
+            name: "RAM".into(),
+            origin: RAM_ORIGIN,
+            length: main_ram,
+        });
+        regions.extend(extra);
+
+        memories.push(Memory {
+            name,
+            feature,
+            regions,
+        });
+    }
+    Ok(memories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mcu(ref_name: &str, family: &str, ram: &[u32], ccm_ram: &[u32], flash: &[u32]) -> mcu::Mcu {
+        mcu::Mcu {
+            ref_name: ref_name.into(),
+            family: family.into(),
+            package: "LQFP64".into(),
+            cores: vec!["Arm Cortex-M4".into()],
+            frequencies: Vec::new(),
+            ram: ram.to_vec(),
+            ccm_ram: ccm_ram.to_vec(),
+            flash: flash.to_vec(),
+            io_nb: 51,
+            voltage: None,
+            temperature: None,
+            ips: Vec::new(),
+            pins: Vec::new(),
+        }
+    }
+
+    fn files(mcus: &[mcu::Mcu], per_feature: bool) -> Vec<(String, String)> {
+        gen_memory_files(mcus, per_feature)
+            .unwrap()
+            .into_iter()
+            .map(|(path, content)| (path.display().to_string(), content))
+            .collect()
+    }
+
+    #[test]
+    fn ccm_ram() {
+        let mcus = [mcu(
+            "STM32F303C(B-C)Tx",
+            "STM32F3",
+            &[40],
+            &[8],
+            &[128, 256],
+        )];
+        let files = files(&mcus, false);
+        assert_eq!(files[0].0, "stm32f303cbtx/memory.x");
+        assert_eq!(files[1].0, "stm32f303cctx/memory.x");
+        assert_eq!(
+            files[1].1,
+            "/* STM32F303CCTx */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  RAM : ORIGIN = 0x20000000, LENGTH = 40K
+  CCMRAM : ORIGIN = 0x10000000, LENGTH = 8K
+}
+"
+        );
+    }
+
+    #[test]
+    fn l4_sram2() {
+        let mcus = [mcu("STM32L476RGTx", "STM32L4", &[128], &[], &[1024])];
+        assert_eq!(
+            files(&mcus, false),
+            [(
+                "stm32l476rgtx/memory.x".into(),
+                "/* STM32L476RGTx */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 1024K
+  RAM : ORIGIN = 0x20000000, LENGTH = 96K
+  SRAM2 : ORIGIN = 0x10000000, LENGTH = 32K
+}
+"
+                .into()
+            )]
+        );
+    }
+
+    #[test]
+    fn g4_sram2_and_ccm_ram() {
+        let mcus = [mcu("STM32G474RETx", "STM32G4", &[128], &[], &[512])];
+        assert_eq!(
+            files(&mcus, false),
+            [(
+                "stm32g474retx/memory.x".into(),
+                "/* STM32G474RETx */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 512K
+  RAM : ORIGIN = 0x20000000, LENGTH = 80K
+  SRAM2 : ORIGIN = 0x20014000, LENGTH = 16K
+  CCMRAM : ORIGIN = 0x10000000, LENGTH = 32K
+}
+"
+                .into()
+            )]
+        );
+    }
+
+    #[test]
+    fn smallest_layout_per_feature() {
+        let mcus = [
+            mcu("STM32F303CBTx", "STM32F3", &[40], &[8], &[128]),
+            mcu("STM32F303RBTx", "STM32F3", &[32], &[8], &[128]),
+        ];
+        assert_eq!(
+            files(&mcus, true),
+            [(
+                "stm32f303xb/memory.x".into(),
+                "/* STM32F303RBTx */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  RAM : ORIGIN = 0x20000000, LENGTH = 32K
+  CCMRAM : ORIGIN = 0x10000000, LENGTH = 8K
+}
+"
+                .into()
+            )]
+        );
+    }
+
+    #[test]
+    fn skipped_families() {
+        let mcus = [
+            mcu("STM32F303CBTx", "STM32F3", &[40], &[8], &[128]),
+            mcu("STM32H743V(G-I)Tx", "STM32H7", &[1024], &[], &[1024, 2048]),
+        ];
+        let files = files(&mcus, false);
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            (
+                "skipped.txt".into(),
+                "STM32H7: STM32H743V(G-I)Tx: unknown RAM layout\n".into()
+            )
+        );
+    }
+}
@@ -1,12 +1,128 @@
+//! Generators turning the CubeMX data into HAL source code.
+//!
+//! The generators in [`generator::GENERATORS`] load what they need from the
+//! database and return the files to write, the modules below do the
+//! rendering.
+
+pub mod adc;
+pub mod cfg;
 pub mod dma;
+pub mod dmamux;
+pub mod doc;
+pub mod emit;
+pub mod format;
+pub mod generator;
 pub mod gpio;
+pub mod memory;
+pub mod region;
+pub mod rules;
+pub mod template;
 
 use crate::cubemx::package::Package;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
 
-pub fn gen_autogen_comment(package: &Package) {
-    println!("// auto-generated using codegen");
-    println!(
-        "// STM32CubeMX DB release: {}",
+pub fn gen_autogen_comment(package: &Package) -> String {
+    format!(
+        "// auto-generated using codegen\n// STM32CubeMX DB release: {}\n",
         package.pack_description.release
-    );
+    )
+}
+
+/// Derives the HAL chip features from an MCU reference name.
+///
+/// `STM32F303C(B-C)Tx` yields `stm32f303xb` and `stm32f303xc`. Lines with
+/// two-letter series, like `STM32WB55CGUx` or `STM32WBA52CEUx`, are
+/// supported as well.
+pub fn mcu_features(ref_name: &str) -> Result<Vec<String>> {
+    static REF_NAME: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"^STM32(?P<line>[A-Z]{3}\d{2}|[A-Z]\d[0-9A-Z]{2}|[A-Z]{2}[0-9A-Z]{2})[0-9A-Z](?P<flash>\([0-9A-Z-]+\)|[0-9A-Z])",
+        )
+        .unwrap()
+    });
+
+    let captures = REF_NAME
+        .captures(ref_name)
+        .with_context(|| format!("invalid MCU name: {}", ref_name))?;
+
+    let line = captures.name("line").unwrap().as_str().to_lowercase();
+    let flash = captures.name("flash").unwrap().as_str();
+    let features = flash
+        .trim_matches(|c| c == '(' || c == ')')
+        .split('-')
+        .map(|size| format!("stm32{line}x{}", size.to_lowercase()))
+        .collect();
+    Ok(features)
+}
+
+/// Derives the feature of an IP version from its chip part and the IP name.
+///
+/// `STM32F303_gpio_v1_0` of the `GPIO` IP yields `gpio-f303`,
+/// `STM32F417_dma_v2_0` of the `DMA` IP `dma-f417`.
+pub fn ip_version_feature(ip_name: &str, version: &str) -> Result<String> {
+    static VERSION: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^STM32(?P<chip>\w+?)_[A-Za-z]+\d*_v\d+_\d+$").unwrap());
+
+    let captures = VERSION
+        .captures(version)
+        .with_context(|| format!("invalid {} IP version: {}", ip_name, version))?;
+
+    let chip = captures.name("chip").unwrap().as_str();
+    Ok(format!(
+        "{}-{}",
+        ip_name.to_lowercase(),
+        chip.to_lowercase()
+    ))
+}
+
+/// Expands an MCU reference name into the names of the chips it covers.
+///
+/// `STM32F303C(B-C)Tx` yields `STM32F303CBTx` and `STM32F303CCTx`.
+pub fn mcu_variants(ref_name: &str) -> Vec<String> {
+    let (start, end) = match (ref_name.find('('), ref_name.find(')')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return vec![ref_name.to_string()],
+    };
+    ref_name[start + 1..end]
+        .split('-')
+        .map(|variant| format!("{}{}{}", &ref_name[..start], variant, &ref_name[end + 1..]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_of_flash_variants() {
+        assert_eq!(
+            mcu_features("STM32F303C(B-C)Tx").unwrap(),
+            ["stm32f303xb", "stm32f303xc"]
+        );
+        assert_eq!(mcu_features("STM32F407VGTx").unwrap(), ["stm32f407xg"]);
+        assert_eq!(mcu_features("STM32H7A3ZITx").unwrap(), ["stm32h7a3xi"]);
+    }
+
+    #[test]
+    fn features_of_two_letter_series() {
+        assert_eq!(mcu_features("STM32WB55CGUx").unwrap(), ["stm32wb55xg"]);
+        assert_eq!(mcu_features("STM32WLE5C8Ux").unwrap(), ["stm32wle5x8"]);
+        assert_eq!(mcu_features("STM32WBA52CEUx").unwrap(), ["stm32wba52xe"]);
+    }
+
+    #[test]
+    fn invalid_mcu_name() {
+        assert!(mcu_features("STM8S003F3Px").is_err());
+    }
+
+    #[test]
+    fn variants() {
+        assert_eq!(
+            mcu_variants("STM32F303C(B-C)Tx"),
+            ["STM32F303CBTx", "STM32F303CCTx"]
+        );
+        assert_eq!(mcu_variants("STM32F407VGTx"), ["STM32F407VGTx"]);
+    }
 }
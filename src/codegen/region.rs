@@ -0,0 +1,132 @@
+use anyhow::{bail, Result};
+
+const BEGIN: &str = "// codegen:begin ";
+const END: &str = "// codegen:end";
+
+/// Replaces the lines between `// codegen:begin <name>` and `// codegen:end`
+/// in `source` with `content`, keeping the markers and everything around them.
+/// `content` takes the line endings of the begin marker.
+pub fn replace(source: &str, name: &str, content: &str) -> Result<String> {
+    let mut out = String::with_capacity(source.len() + content.len());
+    let mut state = State::Before;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        match state {
+            State::Before | State::After => {
+                if trimmed.strip_prefix(BEGIN).map(str::trim) == Some(name) {
+                    if state == State::After {
+                        bail!("region `{}` occurs more than once", name);
+                    }
+                    out.push_str(line);
+                    if !line.ends_with('\n') {
+                        out.push('\n');
+                    }
+                    if line.ends_with("\r\n") {
+                        out.push_str(&content.replace("\r\n", "\n").replace('\n', "\r\n"));
+                        if !content.is_empty() && !content.ends_with('\n') {
+                            out.push_str("\r\n");
+                        }
+                    } else {
+                        out.push_str(content);
+                        if !content.is_empty() && !content.ends_with('\n') {
+                            out.push('\n');
+                        }
+                    }
+                    state = State::Inside;
+                } else {
+                    out.push_str(line);
+                }
+            }
+            State::Inside => {
+                if trimmed == END {
+                    out.push_str(line);
+                    state = State::After;
+                } else if trimmed.starts_with(BEGIN) {
+                    bail!("region `{}` is not closed before `{}`", name, trimmed);
+                }
+            }
+        }
+    }
+
+    match state {
+        State::Before => bail!("region `{}` not found", name),
+        State::Inside => bail!("region `{}` is not closed", name),
+        State::After => Ok(out),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Before,
+    Inside,
+    After,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_region() {
+        let source = "use a;\n// codegen:begin gpio\nold\n// codegen:end\nfn main() {}\n";
+        assert_eq!(
+            replace(source, "gpio", "new\n").unwrap(),
+            "use a;\n// codegen:begin gpio\nnew\n// codegen:end\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_other_regions() {
+        let source = "    // codegen:begin dma\nold dma\n    // codegen:end\n\
+                      // codegen:begin gpio\nold gpio\n// codegen:end\n";
+        assert_eq!(
+            replace(source, "gpio", "new gpio").unwrap(),
+            "    // codegen:begin dma\nold dma\n    // codegen:end\n\
+             // codegen:begin gpio\nnew gpio\n// codegen:end\n"
+        );
+    }
+
+    #[test]
+    fn empty_content() {
+        let source = "// codegen:begin gpio\nold\n// codegen:end";
+        assert_eq!(
+            replace(source, "gpio", "").unwrap(),
+            "// codegen:begin gpio\n// codegen:end"
+        );
+    }
+
+    #[test]
+    fn keeps_crlf() {
+        let source = "use a;\r\n// codegen:begin gpio\r\nold\r\n// codegen:end\r\n";
+        assert_eq!(
+            replace(source, "gpio", "a\nb\n").unwrap(),
+            "use a;\r\n// codegen:begin gpio\r\na\r\nb\r\n// codegen:end\r\n"
+        );
+    }
+
+    #[test]
+    fn missing_region() {
+        let source = "// codegen:begin dma\n// codegen:end\n";
+        assert!(replace(source, "gpio", "").is_err());
+    }
+
+    #[test]
+    fn missing_end_marker() {
+        let source = "// codegen:begin gpio\nold\n";
+        assert!(replace(source, "gpio", "").is_err());
+    }
+
+    #[test]
+    fn nested_region() {
+        let source =
+            "// codegen:begin gpio\n// codegen:begin dma\n// codegen:end\n// codegen:end\n";
+        assert!(replace(source, "gpio", "").is_err());
+    }
+
+    #[test]
+    fn duplicate_region() {
+        let source =
+            "// codegen:begin gpio\n// codegen:end\n// codegen:begin gpio\n// codegen:end\n";
+        assert!(replace(source, "gpio", "").is_err());
+    }
+}
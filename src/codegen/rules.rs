@@ -0,0 +1,128 @@
+//! Rules deciding the output type and `NoPin` support of the `pin!` types
+//! written by the GPIO generator. See `rules.toml` for the format.
+
+use crate::cubemx::selector::glob_regex;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+/// The rules shipped with `codegen`.
+pub const DEFAULT: &str = include_str!("../../rules.toml");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Otype {
+    /// The pin type always uses this output type.
+    Fixed(String),
+    /// The pin type uses this output type unless another one is given.
+    Default(String),
+}
+
+#[derive(Debug)]
+pub struct Rules {
+    otype: Vec<(Pattern, Otype)>,
+    nopin: Vec<Pattern>,
+}
+
+impl Rules {
+    /// Loads the rules from a file, or the shipped defaults without one.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Self::parse(&text).with_context(|| format!("invalid rules in {}", path.display()))
+            }
+            None => Self::parse(DEFAULT),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let raw: RawRules = toml::from_str(text)?;
+
+        let mut otype = Vec::new();
+        for rule in raw.otype {
+            let value = match (rule.fixed, rule.default) {
+                (Some(fixed), None) => Otype::Fixed(fixed),
+                (None, Some(default)) => Otype::Default(default),
+                _ => bail!(
+                    "otype rule for {:?} needs either `fixed` or `default`",
+                    rule.pattern.peripheral
+                ),
+            };
+            otype.push((Pattern::new(&rule.pattern)?, value));
+        }
+        let nopin = raw.nopin.iter().map(Pattern::new).collect::<Result<_>>()?;
+
+        Ok(Self { otype, nopin })
+    }
+
+    /// Returns the output type of the first rule matching a signal.
+    pub fn otype(&self, peripheral: &str, signal: &str) -> Option<&Otype> {
+        self.otype
+            .iter()
+            .find(|(pattern, _)| pattern.matches(peripheral, signal))
+            .map(|(_, otype)| otype)
+    }
+
+    /// Returns whether a signal accepts `NoPin`.
+    pub fn nopin(&self, peripheral: &str, signal: &str) -> bool {
+        self.nopin.iter().any(|p| p.matches(peripheral, signal))
+    }
+}
+
+#[derive(Debug)]
+struct Pattern {
+    peripherals: Vec<Regex>,
+    signals: Vec<Regex>,
+}
+
+impl Pattern {
+    fn new(raw: &RawPattern) -> Result<Self> {
+        let compile = |globs: &[String]| -> Result<Vec<Regex>> {
+            globs
+                .iter()
+                .map(|g| {
+                    Regex::new(&glob_regex(g)).with_context(|| format!("invalid pattern: {}", g))
+                })
+                .collect()
+        };
+        if raw.peripheral.is_empty() {
+            bail!("rule without peripheral patterns");
+        }
+        Ok(Self {
+            peripherals: compile(&raw.peripheral)?,
+            signals: compile(&raw.signal)?,
+        })
+    }
+
+    /// A pattern without signals matches all signals of its peripherals.
+    fn matches(&self, peripheral: &str, signal: &str) -> bool {
+        self.peripherals.iter().any(|re| re.is_match(peripheral))
+            && (self.signals.is_empty() || self.signals.iter().any(|re| re.is_match(signal)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRules {
+    #[serde(default)]
+    otype: Vec<RawOtype>,
+    #[serde(default)]
+    nopin: Vec<RawPattern>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawOtype {
+    #[serde(flatten)]
+    pattern: RawPattern,
+    fixed: Option<String>,
+    default: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawPattern {
+    peripheral: Vec<String>,
+    #[serde(default)]
+    signal: Vec<String>,
+}
@@ -0,0 +1,157 @@
+//! Renders GPIO mappings with user-supplied templates, for HALs with other
+//! macro conventions than the built-in renderer. See `templates/` for
+//! examples.
+
+use crate::codegen::{
+    cfg::Universe,
+    gpio::{Chip, Mappings, Pin},
+    rules::{Otype, Rules},
+};
+use anyhow::{Context, Result};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::{collections::BTreeSet, fs, path::Path};
+
+/// The data available to templates.
+#[derive(Debug, Serialize)]
+struct Data<'a> {
+    /// Features of all GPIO IP versions.
+    features: Vec<&'a str>,
+    /// The ports of every IP version.
+    chips: &'a [Chip],
+    /// The alternate functions of all IP versions by peripheral.
+    peripherals: Vec<Peripheral<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Peripheral<'a> {
+    name: &'a str,
+    /// Name without the instance number, e.g. `usart` for `usart2`.
+    kind: &'a str,
+    features: BTreeSet<&'a str>,
+    functions: Vec<Function<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Function<'a> {
+    name: &'a str,
+    features: BTreeSet<&'a str>,
+    /// Output type from the rules, if it is fixed.
+    fixed_otype: Option<&'a str>,
+    /// Output type from the rules, if it is a default.
+    default_otype: Option<&'a str>,
+    nopin: bool,
+    pins: Vec<FunctionPin<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionPin<'a> {
+    #[serde(flatten)]
+    pin: Pin,
+    features: &'a BTreeSet<String>,
+}
+
+/// Renders the template in `path`.
+pub fn render_file(
+    path: &Path,
+    mappings: &Mappings,
+    rules: &Rules,
+    complete: bool,
+) -> Result<String> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("failed to read template {}", path.display()))?;
+    render(
+        &path.display().to_string(),
+        &source,
+        mappings,
+        rules,
+        complete,
+    )
+}
+
+/// Renders a template, `name` is used in error messages.
+///
+/// Besides the data, templates can call `cfg(features, indent)`, which returns
+/// the `#[cfg]` attribute for a list of features minimised against all
+/// features if `complete`, or an empty string if no attribute is needed.
+pub fn render(
+    name: &str,
+    source: &str,
+    mappings: &Mappings,
+    rules: &Rules,
+    complete: bool,
+) -> Result<String> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_keep_trailing_newline(true);
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+
+    let universe =
+        Universe::new(mappings.chips.iter().map(|c| c.feature.clone())).complete(complete);
+    env.add_function(
+        "cfg",
+        move |features: Vec<String>, indent: Option<String>| match universe.cfg(&features) {
+            Some(cfg) => cfg.attribute(indent.as_deref().unwrap_or("")),
+            None => String::new(),
+        },
+    );
+
+    env.add_template(name, source)
+        .with_context(|| format!("invalid template {}", name))?;
+    let content = env
+        .get_template(name)?
+        .render(data(mappings, rules))
+        .with_context(|| format!("failed to render template {}", name))?;
+    Ok(content)
+}
+
+fn data<'a>(mappings: &'a Mappings, rules: &'a Rules) -> Data<'a> {
+    let peripherals = mappings
+        .peripherals
+        .iter()
+        .map(|(per, functions)| {
+            let functions: Vec<_> = functions
+                .iter()
+                .map(|(function, pins)| {
+                    let otype = rules.otype(per, function);
+                    Function {
+                        name: function,
+                        features: pins.values().flatten().map(String::as_str).collect(),
+                        fixed_otype: match otype {
+                            Some(Otype::Fixed(otype)) => Some(otype),
+                            _ => None,
+                        },
+                        default_otype: match otype {
+                            Some(Otype::Default(otype)) => Some(otype),
+                            _ => None,
+                        },
+                        nopin: rules.nopin(per, function),
+                        pins: pins
+                            .iter()
+                            .map(|(pin, features)| FunctionPin {
+                                pin: *pin,
+                                features,
+                            })
+                            .collect(),
+                    }
+                })
+                .collect();
+            Peripheral {
+                name: per,
+                kind: per.trim_end_matches(|c: char| c.is_ascii_digit()),
+                features: functions
+                    .iter()
+                    .flat_map(|f| f.features.iter().copied())
+                    .collect(),
+                functions,
+            }
+        })
+        .collect();
+
+    Data {
+        features: mappings.chips.iter().map(|c| c.feature.as_str()).collect(),
+        chips: &mappings.chips,
+        peripherals,
+    }
+}
@@ -5,6 +5,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
+/// The root directory of an STM32CubeMX database, e.g. `STM32CubeMX/db`.
 pub struct Db {
     root: PathBuf,
 }
@@ -14,6 +15,8 @@ impl Db {
         Self { root: root.into() }
     }
 
+    /// Deserializes an XML file, `name` is relative to the root and has no
+    /// extension.
     pub fn load<'de, P: AsRef<Path>, T: Deserialize<'de>>(&self, name: P) -> Result<T> {
         let name = name.as_ref();
         let mut path = self.root.join(name);
@@ -24,6 +27,7 @@ impl Db {
             .with_context(|| format!("cannot parse DB file: {:?}", path))
     }
 
+    /// Like [`Db::load`], relative to the `mcu` directory.
     pub fn load_mcu<'de, P: AsRef<Path>, T: Deserialize<'de>>(&self, name: P) -> Result<T> {
         let mut mcu_path = PathBuf::new();
         mcu_path.push("mcu");
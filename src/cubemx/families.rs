@@ -1,19 +1,11 @@
 use crate::cubemx::Db;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::Deserialize;
 
 pub fn load(db: &Db) -> Result<Families> {
     db.load_mcu("families")
 }
 
-pub fn load_f3(db: &Db, fname: &str) -> Result<Family> {
-    load(db)?
-        .families
-        .into_iter()
-        .find(|f| f.name == fname)
-        .context("STM32F3 family not found")
-}
-
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Families {
@@ -41,4 +33,8 @@ pub struct SubFamily {
 #[serde(rename_all = "PascalCase")]
 pub struct Mcu {
     pub name: String,
+    pub ref_name: String,
+    pub package_name: String,
+    #[serde(rename = "Core", default)]
+    pub cores: Vec<String>,
 }
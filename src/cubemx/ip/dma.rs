@@ -1,10 +1,15 @@
 use crate::cubemx::Db;
 use anyhow::Result;
 use serde::Deserialize;
-use std::{path::PathBuf, fmt::Display, str::FromStr};
+use std::path::PathBuf;
 
-pub fn load(db: &Db, version: &str) -> Result<Ip> {
-    let name = format!("DMA-{}_Modes", version);
+/// Names of the DMA controller IPs: the general purpose DMA, and the basic and
+/// master DMA of the H7.
+pub const NAMES: &[&str] = &["DMA", "BDMA", "MDMA"];
+
+/// Loads the mode file of a DMA controller IP, `ip_name` is one of [`NAMES`].
+pub fn load(db: &Db, ip_name: &str, version: &str) -> Result<Ip> {
+    let name = format!("{}-{}_Modes", ip_name, version);
     let ip_path: PathBuf = ["IP", &name].iter().collect();
     db.load_mcu(&ip_path)
 }
@@ -12,6 +17,8 @@ pub fn load(db: &Db, version: &str) -> Result<Ip> {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Ip {
+    /// `DMA`, `BDMA` or `MDMA`.
+    pub name: String,
     pub version: String,
     #[serde(rename = "RefMode")]
     pub modes: Vec<RefMode>,
@@ -29,6 +36,14 @@ pub struct RefMode {
     pub parameters: Vec<Parameter>,
 }
 
+impl RefMode {
+    /// Whether this is the request of a peripheral, `DMA_Request` and the
+    /// like of the other controllers, except for memory to memory transfers.
+    pub fn is_request(&self) -> bool {
+        self.basemode.ends_with("DMA_Request") && self.name != "MEMTOMEM"
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Parameter {
@@ -40,7 +55,6 @@ pub struct Parameter {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ModeLogicOperator {
-    pub name: Logic,
     #[serde(rename = "Mode")]
     pub modes: Vec<Mode>,
 }
@@ -52,33 +66,3 @@ pub struct Mode {
     #[serde(rename = "ModeLogicOperator", default)]
     pub operators: Vec<ModeLogicOperator>,
 }
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
-#[serde(rename_all = "UPPERCASE")]
-pub enum Logic {
-    Or,
-    And,
-    Xor
-}
-
-impl FromStr for Logic {
-    type Err = ();
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s {
-            "OR" => Ok(Self::Or),
-            "AND" => Ok(Self::And),
-            "XOR" => Ok(Self::Xor),
-            _ => Err(()),
-        }
-    }
-}
-
-impl Display for Logic {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Or => f.write_str("OR"),
-            Self::And => f.write_str("AND"),
-            Self::Xor => f.write_str("XOR"),
-        }
-    }
-}
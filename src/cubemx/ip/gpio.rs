@@ -75,6 +75,22 @@ impl PinSignal {
     }
 }
 
+/// Returns whether a pin starts in its debug function (AF0) after reset
+/// instead of as a GPIO.
+pub fn is_debug_pin(port: char, number: u8) -> bool {
+    matches!(
+        (port, number),
+        ('A', 13) | ('A', 14) | ('A', 15) | ('B', 3) | ('B', 4)
+    )
+}
+
+/// Splits a signal name into its peripheral and function, e.g. `TIM1_CH1N`
+/// into `TIM1` and `CH1N`. Returns `None` for signals without a function,
+/// such as `EVENTOUT`.
+pub fn split_signal(name: &str) -> Option<(&str, &str)> {
+    name.split_once('_')
+}
+
 fn parse_af(s: &str) -> Result<u8> {
     static AF: Lazy<Regex> = Lazy::new(|| Regex::new(r"^GPIO_AF(?P<nr>\d{1,2})_\w+$").unwrap());
 
@@ -1,22 +1,196 @@
 use crate::cubemx::Db;
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 pub fn load(db: &Db, name: &str) -> Result<Mcu> {
     db.load_mcu(name)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Mcu {
     pub ref_name: String,
+    pub family: String,
+    pub package: String,
+    #[serde(rename = "Core")]
+    pub cores: Vec<String>,
+    /// Maximum core frequency in MHz, one entry per core.
+    #[serde(rename = "Frequency", default)]
+    pub frequencies: Vec<u32>,
+    /// RAM size in KiB, one entry per variant of `ref_name`.
+    #[serde(rename = "Ram", default)]
+    pub ram: Vec<u32>,
+    #[serde(rename = "CCMRam", default)]
+    pub ccm_ram: Vec<u32>,
+    /// Flash size in KiB, one entry per variant of `ref_name`.
+    #[serde(rename = "Flash", default)]
+    pub flash: Vec<u32>,
+    #[serde(rename = "IONb")]
+    pub io_nb: u32,
+    pub voltage: Option<Range>,
+    pub temperature: Option<Range>,
     #[serde(rename = "IP")]
     pub ips: Vec<Ip>,
+    #[serde(rename = "Pin", default)]
+    pub pins: Vec<Pin>,
 }
 
-#[derive(Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Range {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, Ord, PartialEq, PartialOrd)]
 #[serde(rename_all = "PascalCase")]
 pub struct Ip {
     pub name: String,
     pub version: String,
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Pin {
+    pub name: String,
+    pub position: String,
+    #[serde(rename = "Type")]
+    pub kind: PinKind,
+    #[serde(rename = "Signal", default)]
+    pub signals: Vec<Signal>,
+}
+
+impl Pin {
+    /// Returns the normalized GPIO name, or `None` for pins that are not
+    /// GPIOs (supply, reset, boot, ...).
+    pub fn gpio(&self) -> Option<PinName> {
+        match self.kind {
+            PinKind::Io | PinKind::MonoIo => PinName::parse(&self.name).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PinKind {
+    #[serde(rename = "I/O")]
+    Io,
+    #[serde(rename = "MonoIO")]
+    MonoIo,
+    Power,
+    Reset,
+    Boot,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Signal {
+    pub name: String,
+}
+
+/// A GPIO name split into its parts, e.g. `PC14-OSC32_IN` into port `C`,
+/// number 14 and suffix `OSC32_IN`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PinName {
+    pub port: char,
+    pub number: u8,
+    pub suffix: Option<String>,
+}
+
+impl PinName {
+    pub fn parse(name: &str) -> Result<Self> {
+        static PIN_NAME: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^P(?P<port>[A-Z])(?P<nr>\d{1,2})(?P<suffix>(?:[-_ ].*|[A-Z].*)?)$")
+                .unwrap()
+        });
+
+        let captures = PIN_NAME
+            .captures(name)
+            .with_context(|| format!("invalid pin name: {}", name))?;
+
+        let port = captures.name("port").unwrap().as_str().parse()?;
+        let number = captures.name("nr").unwrap().as_str().parse()?;
+        let suffix = captures
+            .name("suffix")
+            .unwrap()
+            .as_str()
+            .trim_start_matches(['-', '_', ' ']);
+        let suffix = if suffix.is_empty() {
+            None
+        } else {
+            Some(suffix.to_string())
+        };
+
+        Ok(Self {
+            port,
+            number,
+            suffix,
+        })
+    }
+}
+
+impl fmt::Display for PinName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "P{}{}", self.port, self.number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(port: char, number: u8, suffix: Option<&str>) -> PinName {
+        PinName {
+            port,
+            number,
+            suffix: suffix.map(String::from),
+        }
+    }
+
+    #[test]
+    fn plain_pin_name() {
+        assert_eq!(PinName::parse("PA1").unwrap(), pin('A', 1, None));
+    }
+
+    #[test]
+    fn pin_name_with_function() {
+        assert_eq!(
+            PinName::parse("PC14-OSC32_IN").unwrap(),
+            pin('C', 14, Some("OSC32_IN"))
+        );
+        assert_eq!(
+            PinName::parse("PF0-OSC_IN").unwrap(),
+            pin('F', 0, Some("OSC_IN"))
+        );
+    }
+
+    #[test]
+    fn remapped_pin_name() {
+        assert_eq!(
+            PinName::parse("PA11 [PA9]").unwrap(),
+            pin('A', 11, Some("[PA9]"))
+        );
+    }
+
+    #[test]
+    fn analog_switch_pin_name() {
+        assert_eq!(PinName::parse("PB2_C").unwrap(), pin('B', 2, Some("C")));
+        assert_eq!(PinName::parse("PA0_C").unwrap(), pin('A', 0, Some("C")));
+    }
+
+    #[test]
+    fn power_pins_are_rejected() {
+        assert!(PinName::parse("VDD").is_err());
+        assert!(PinName::parse("PDR_ON").is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(PinName::parse("PC14-OSC32_IN").unwrap().to_string(), "PC14");
+    }
+}
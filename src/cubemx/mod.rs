@@ -1,47 +1,100 @@
+//! Loading of the STM32CubeMX MCU database.
+//!
+//! The database is opened with [`Db::new`], MCUs are picked from
+//! `families.xml` with a [`Selector`].
+
 pub mod families;
 pub mod ip;
 pub mod mcu;
 pub mod package;
+pub mod selector;
 
 mod db;
 
 pub use db::Db;
+pub use selector::Selector;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
 
-pub fn load_f3_mcus(db: &Db, fname: &str) -> Result<Vec<mcu::Mcu>> {
-    families::load_f3(db, fname)?
-        .sub_families
+/// Loads the description files of the selected MCUs.
+pub fn load_mcus(db: &Db, selector: &Selector) -> Result<Vec<mcu::Mcu>> {
+    let families = families::load(db)?;
+    // Several reference names share one MCU file, load each file only once.
+    let mut names: Vec<_> = selector
+        .select(&families)
         .into_iter()
-        .flat_map(|subfamily| subfamily.mcus.into_iter())
-        .map(|mcu_| mcu::load(db, &mcu_.name))
-        .collect()
+        .map(|mcu_| &mcu_.name)
+        .collect();
+    if names.is_empty() {
+        bail!("no MCU matches the selection");
+    }
+    names.sort();
+    names.dedup();
+
+    names.iter().map(|name| mcu::load(db, name)).collect()
 }
 
-pub fn load_f3_ips(db: &Db, ip_name: &str, fname: &str) -> Result<Vec<mcu::Ip>> {
-    let f3_mcus = load_f3_mcus(db, fname)?;
-    let mut ips: Vec<_> = f3_mcus
-        .into_iter()
-        .flat_map(|mcu| mcu.ips.into_iter())
+/// Loads a single MCU by its reference name (`STM32F303CBTx`) or by the name
+/// of its description file (`STM32F303C(B-C)Tx`).
+pub fn load_mcu(db: &Db, name: &str) -> Result<mcu::Mcu> {
+    let families = families::load(db)?;
+    let mcu_ = families
+        .families
+        .iter()
+        .flat_map(|family| family.sub_families.iter())
+        .flat_map(|subfamily| subfamily.mcus.iter())
+        .find(|mcu_| mcu_.name == name || mcu_.ref_name == name)
+        .with_context(|| format!("MCU {} not found", name))?;
+    mcu::load(db, &mcu_.name)
+}
+
+/// Collects the distinct versions of an IP used by the selected MCUs.
+pub fn load_ips(db: &Db, ip_name: &str, selector: &Selector) -> Result<Vec<mcu::Ip>> {
+    Ok(distinct_ips(&load_mcus(db, selector)?, ip_name))
+}
+
+/// Collects the distinct versions of an IP used by `mcus`.
+pub fn distinct_ips(mcus: &[mcu::Mcu], ip_name: &str) -> Vec<mcu::Ip> {
+    let mut ips: Vec<_> = mcus
+        .iter()
+        .flat_map(|mcu| mcu.ips.iter())
         .filter(|ip| ip.name == ip_name)
+        .cloned()
         .collect();
 
     ips.sort();
     ips.dedup();
 
-    Ok(ips)
+    ips
+}
+
+/// Returns the families of `mcus` using each IP version.
+pub fn version_families(mcus: &[mcu::Mcu]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut families = BTreeMap::<String, BTreeSet<String>>::new();
+    for mcu in mcus {
+        for ip in &mcu.ips {
+            families
+                .entry(ip.version.clone())
+                .or_default()
+                .insert(mcu.family.clone());
+        }
+    }
+    families
 }
 
-pub fn load_f3_gpio_ips(db: &Db, fname: &str) -> Result<Vec<ip::gpio::Ip>> {
-    load_f3_ips(db, "GPIO", fname)?
+pub fn load_gpio_ips(db: &Db, selector: &Selector) -> Result<Vec<ip::gpio::Ip>> {
+    load_ips(db, "GPIO", selector)?
         .into_iter()
         .map(|ip_| ip::gpio::load(db, &ip_.version))
         .collect()
 }
 
-pub fn load_f3_dma_ips(db: &Db, fname: &str) -> Result<Vec<ip::dma::Ip>> {
-    load_f3_ips(db, "DMA", fname)?
+/// Loads the versions of a DMA controller IP used by `mcus`, see
+/// [`ip::dma::NAMES`].
+pub fn load_dma_ips(db: &Db, ip_name: &str, mcus: &[mcu::Mcu]) -> Result<Vec<ip::dma::Ip>> {
+    distinct_ips(mcus, ip_name)
         .into_iter()
-        .map(|ip_| ip::dma::load(db, &ip_.version))
+        .map(|ip_| ip::dma::load(db, ip_name, &ip_.version))
         .collect()
 }
@@ -0,0 +1,244 @@
+use crate::cubemx::families::{Families, Family, Mcu, SubFamily};
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+
+/// Selects MCUs from `families.xml`.
+///
+/// Every criterion matches if any of its values matches, an empty criterion
+/// matches everything. An MCU is selected if all criteria match.
+#[derive(Debug, Default)]
+pub struct Selector {
+    families: Vec<String>,
+    sub_families: Vec<String>,
+    mcus: Vec<Regex>,
+    packages: Vec<String>,
+    cores: Vec<String>,
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects a family, e.g. `STM32F3` or `F3`.
+    pub fn family(mut self, name: &str) -> Self {
+        self.families.push(normalize(name));
+        self
+    }
+
+    /// Selects a sub-family, e.g. `STM32F303` or `F303`.
+    pub fn sub_family(mut self, name: &str) -> Self {
+        self.sub_families.push(normalize(name));
+        self
+    }
+
+    /// Selects MCUs whose name matches a glob pattern, e.g. `STM32F303?(B-C)*`.
+    pub fn mcu_glob(self, glob: &str) -> Result<Self> {
+        self.mcu_regex(&glob_regex(glob))
+    }
+
+    /// Selects MCUs whose name matches a regular expression, ignoring case
+    /// like the other criteria.
+    pub fn mcu_regex(mut self, re: &str) -> Result<Self> {
+        let re = RegexBuilder::new(re)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("invalid MCU pattern: {}", re))?;
+        self.mcus.push(re);
+        Ok(self)
+    }
+
+    /// Selects MCUs in a package, e.g. `LQFP48`.
+    pub fn package(mut self, name: &str) -> Self {
+        self.packages.push(name.to_uppercase());
+        self
+    }
+
+    /// Selects MCUs with a core, e.g. `Cortex-M4` or `M4`.
+    pub fn core(mut self, name: &str) -> Self {
+        self.cores.push(name.to_uppercase());
+        self
+    }
+
+    /// Returns whether whole families are selected, i.e. whether the selected
+    /// MCUs cover every feature of the HAL crates for these families.
+    pub fn is_complete(&self) -> bool {
+        self.sub_families.is_empty()
+            && self.mcus.is_empty()
+            && self.packages.is_empty()
+            && self.cores.is_empty()
+    }
+
+    pub fn matches_family(&self, family: &Family) -> bool {
+        any(&self.families, |f| *f == family.name.to_uppercase())
+    }
+
+    pub fn matches(&self, family: &Family, sub_family: &SubFamily, mcu: &Mcu) -> bool {
+        self.matches_family(family)
+            && any(&self.sub_families, |s| *s == sub_family.name.to_uppercase())
+            && (self.mcus.is_empty()
+                || self
+                    .mcus
+                    .iter()
+                    .any(|re| re.is_match(&mcu.ref_name) || re.is_match(&mcu.name)))
+            && any(&self.packages, |p| *p == mcu.package_name.to_uppercase())
+            && any(&self.cores, |c| {
+                mcu.cores
+                    .iter()
+                    .any(|core| core.to_uppercase().contains(c.as_str()))
+            })
+    }
+
+    pub fn select<'a>(&self, families: &'a Families) -> Vec<&'a Mcu> {
+        let mut mcus = Vec::new();
+        for family in &families.families {
+            for sub_family in &family.sub_families {
+                for mcu in &sub_family.mcus {
+                    if self.matches(family, sub_family, mcu) {
+                        mcus.push(mcu);
+                    }
+                }
+            }
+        }
+        mcus
+    }
+}
+
+fn any(values: &[String], f: impl Fn(&String) -> bool) -> bool {
+    values.is_empty() || values.iter().any(f)
+}
+
+fn normalize(name: &str) -> String {
+    let name = name.to_uppercase();
+    if name.starts_with("STM32") {
+        name
+    } else {
+        format!("STM32{}", name)
+    }
+}
+
+/// Translates a case-insensitive glob pattern, where `*` matches any text and
+/// `?` a single character, into an anchored regular expression.
+pub fn glob_regex(glob: &str) -> String {
+    let mut re = String::from("(?i)^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(name: &str) -> Family {
+        Family {
+            name: name.into(),
+            sub_families: Vec::new(),
+        }
+    }
+
+    fn sub_family(name: &str) -> SubFamily {
+        SubFamily {
+            name: name.into(),
+            mcus: Vec::new(),
+        }
+    }
+
+    fn mcu(name: &str, ref_name: &str, package: &str, core: &str) -> Mcu {
+        Mcu {
+            name: name.into(),
+            ref_name: ref_name.into(),
+            package_name: package.into(),
+            cores: vec![core.into()],
+        }
+    }
+
+    #[test]
+    fn family_names() {
+        let f3 = family("STM32F3");
+        assert!(Selector::new().matches_family(&f3));
+        assert!(Selector::new().family("STM32F3").matches_family(&f3));
+        assert!(Selector::new().family("f3").matches_family(&f3));
+        assert!(Selector::new()
+            .family("F4")
+            .family("F3")
+            .matches_family(&f3));
+        assert!(!Selector::new().family("F4").matches_family(&f3));
+    }
+
+    #[test]
+    fn all_criteria_must_match() {
+        let (f3, f303) = (family("STM32F3"), sub_family("STM32F303"));
+        let cb = mcu(
+            "STM32F303C(B-C)Tx",
+            "STM32F303CBTx",
+            "LQFP48",
+            "Arm Cortex-M4",
+        );
+        let selector = Selector::new()
+            .family("F3")
+            .sub_family("f303")
+            .package("lqfp48")
+            .core("M4");
+        assert!(selector.matches(&f3, &f303, &cb));
+        // A repeated criterion matches any of its values.
+        assert!(selector.package("LQFP64").matches(&f3, &f303, &cb));
+        let selector = Selector::new().family("F3").core("M0");
+        assert!(!selector.matches(&f3, &f303, &cb));
+    }
+
+    #[test]
+    fn mcu_patterns() {
+        let (f3, f303) = (family("STM32F3"), sub_family("STM32F303"));
+        let cb = mcu(
+            "STM32F303C(B-C)Tx",
+            "STM32F303CBTx",
+            "LQFP48",
+            "Arm Cortex-M4",
+        );
+        let matches = |selector: Selector| selector.matches(&f3, &f303, &cb);
+        // Both the reference name and the file name are matched.
+        assert!(matches(Selector::new().mcu_glob("STM32F303?B*").unwrap()));
+        assert!(matches(
+            Selector::new().mcu_glob("STM32F303?(B-C)*").unwrap()
+        ));
+        assert!(matches(Selector::new().mcu_glob("stm32f303cbtx").unwrap()));
+        assert!(!matches(Selector::new().mcu_glob("STM32F303?C").unwrap()));
+        assert!(matches(Selector::new().mcu_regex("^stm32f30[23]").unwrap()));
+        assert!(!matches(Selector::new().mcu_regex("^STM32F302").unwrap()));
+        assert!(Selector::new().mcu_regex("(").is_err());
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert_eq!(
+            glob_regex("STM32F303?(B-C)*"),
+            r"(?i)^STM32F303.\(B\-C\).*$"
+        );
+        assert_eq!(glob_regex("STM32L4+.x"), r"(?i)^STM32L4\+\.x$");
+        let re = Regex::new(&glob_regex("STM32F3*x?")).unwrap();
+        assert!(re.is_match("STM32F303CBTx6"));
+        assert!(!re.is_match("STM32F303CBTx"));
+        assert!(!re.is_match("XSTM32F303CBTx6"));
+    }
+
+    #[test]
+    fn completeness() {
+        assert!(Selector::new().family("F3").is_complete());
+        assert!(!Selector::new()
+            .family("F3")
+            .sub_family("F303")
+            .is_complete());
+        assert!(!Selector::new()
+            .mcu_glob("STM32F303*")
+            .unwrap()
+            .is_complete());
+        assert!(!Selector::new().package("LQFP48").is_complete());
+    }
+}
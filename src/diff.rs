@@ -0,0 +1,349 @@
+use crate::codegen::{dma::get_mode_maps, dmamux};
+use crate::cubemx::{self, ip, Db, Selector};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// Differences between two CubeMX database releases, by family.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub families: BTreeMap<String, FamilyReport>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FamilyReport {
+    pub added_mcus: Vec<String>,
+    pub removed_mcus: Vec<String>,
+    /// Changes by IP name.
+    pub ips: BTreeMap<String, IpReport>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IpReport {
+    pub versions: Vec<VersionChange>,
+    pub pins: Vec<PinChange>,
+    pub requests: Vec<RequestChange>,
+    /// IP versions that could not be compared, with the reason.
+    pub errors: Vec<String>,
+}
+
+/// An MCU whose IP changed its version.
+#[derive(Debug, Serialize)]
+pub struct VersionChange {
+    pub mcu: String,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// A pin of a GPIO IP version gaining or losing alternate functions.
+#[derive(Debug, Serialize)]
+pub struct PinChange {
+    pub version: String,
+    pub pin: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// A DMA request of a DMA IP version moving to another channel or request
+/// number, or to another DMAMUX request.
+#[derive(Debug, Serialize)]
+pub struct RequestChange {
+    pub version: String,
+    pub request: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// MCU reference names and description files of a DB, by family.
+type FamilyMcus = BTreeMap<String, BTreeMap<String, String>>;
+
+pub fn diff(old: &Db, new: &Db, selector: &Selector) -> Result<Report> {
+    let old_families = family_mcus(old, selector)?;
+    let new_families = family_mcus(new, selector)?;
+
+    let empty = BTreeMap::new();
+    let names: BTreeSet<_> = old_families.keys().chain(new_families.keys()).collect();
+
+    let mut report = Report::default();
+    for name in names {
+        let old_mcus = old_families.get(name).unwrap_or(&empty);
+        let new_mcus = new_families.get(name).unwrap_or(&empty);
+        let family = diff_family(old, new, old_mcus, new_mcus)?;
+        if !family.is_empty() {
+            report.families.insert(name.clone(), family);
+        }
+    }
+    Ok(report)
+}
+
+fn family_mcus(db: &Db, selector: &Selector) -> Result<FamilyMcus> {
+    let families = cubemx::families::load(db)?;
+    let mut mcus = FamilyMcus::new();
+    for family in &families.families {
+        for sub_family in &family.sub_families {
+            for mcu in &sub_family.mcus {
+                if selector.matches(family, sub_family, mcu) {
+                    mcus.entry(family.name.clone())
+                        .or_default()
+                        .insert(mcu.ref_name.clone(), mcu.name.clone());
+                }
+            }
+        }
+    }
+    Ok(mcus)
+}
+
+fn diff_family(
+    old: &Db,
+    new: &Db,
+    old_mcus: &BTreeMap<String, String>,
+    new_mcus: &BTreeMap<String, String>,
+) -> Result<FamilyReport> {
+    let mut report = FamilyReport {
+        added_mcus: difference(new_mcus.keys(), old_mcus.keys()),
+        removed_mcus: difference(old_mcus.keys(), new_mcus.keys()),
+        ips: BTreeMap::new(),
+    };
+
+    let old_ips = load_ips(old, old_mcus)?;
+    let new_ips = load_ips(new, new_mcus)?;
+
+    for (file, old_versions) in &old_ips {
+        let new_versions = match new_ips.get(file) {
+            Some(versions) => versions,
+            None => continue,
+        };
+        let empty = BTreeSet::new();
+        let ip_names: BTreeSet<_> = old_versions.keys().chain(new_versions.keys()).collect();
+        for ip_name in ip_names {
+            let o = old_versions.get(ip_name).unwrap_or(&empty);
+            let n = new_versions.get(ip_name).unwrap_or(&empty);
+            if o != n {
+                report
+                    .ips
+                    .entry(ip_name.clone())
+                    .or_default()
+                    .versions
+                    .push(VersionChange {
+                        mcu: file.clone(),
+                        old: o.iter().cloned().collect(),
+                        new: n.iter().cloned().collect(),
+                    });
+            }
+        }
+    }
+
+    for version in common_versions(&old_ips, &new_ips, "GPIO") {
+        let pins = diff_gpio(
+            &ip::gpio::load(old, &version)?,
+            &ip::gpio::load(new, &version)?,
+        );
+        if !pins.is_empty() {
+            report
+                .ips
+                .entry("GPIO".into())
+                .or_default()
+                .pins
+                .extend(pins);
+        }
+    }
+    for ip_name in ip::dma::NAMES {
+        for version in common_versions(&old_ips, &new_ips, ip_name) {
+            let requests = ip::dma::load(old, ip_name, &version)
+                .and_then(|o| diff_dma(&o, &ip::dma::load(new, ip_name, &version)?));
+            match requests {
+                Ok(requests) if requests.is_empty() => {}
+                Ok(requests) => report
+                    .ips
+                    .entry(ip_name.to_string())
+                    .or_default()
+                    .requests
+                    .extend(requests),
+                Err(e) => report
+                    .ips
+                    .entry(ip_name.to_string())
+                    .or_default()
+                    .errors
+                    .push(format!("{}: {:#}", version, e)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// IP versions by IP name, by MCU description file.
+type McuIps = BTreeMap<String, BTreeMap<String, BTreeSet<String>>>;
+
+fn load_ips(db: &Db, mcus: &BTreeMap<String, String>) -> Result<McuIps> {
+    let files: BTreeSet<_> = mcus.values().collect();
+    let mut ips = McuIps::new();
+    for file in files {
+        let mcu = cubemx::mcu::load(db, file)?;
+        let versions = ips.entry(file.clone()).or_default();
+        for ip in mcu.ips {
+            versions.entry(ip.name).or_default().insert(ip.version);
+        }
+    }
+    Ok(ips)
+}
+
+/// Versions of an IP used by MCUs of both releases.
+fn common_versions(old: &McuIps, new: &McuIps, ip_name: &str) -> BTreeSet<String> {
+    let versions = |ips: &McuIps| -> BTreeSet<String> {
+        ips.values()
+            .filter_map(|versions| versions.get(ip_name))
+            .flatten()
+            .cloned()
+            .collect()
+    };
+    versions(old)
+        .intersection(&versions(new))
+        .cloned()
+        .collect()
+}
+
+fn diff_gpio(old: &ip::gpio::Ip, new: &ip::gpio::Ip) -> Vec<PinChange> {
+    let afs = |ip: &ip::gpio::Ip| -> BTreeMap<String, BTreeSet<String>> {
+        let mut pins = BTreeMap::<String, BTreeSet<String>>::new();
+        for pin in &ip.pins {
+            let entry = pins.entry(pin.name.clone()).or_default();
+            for signal in &pin.pin_signals {
+                if let Ok((af, name)) = signal.af() {
+                    entry.insert(format!("AF{} {}", af, name));
+                }
+            }
+        }
+        pins
+    };
+    let old_afs = afs(old);
+    let new_afs = afs(new);
+
+    let empty = BTreeSet::new();
+    let pins: BTreeSet<_> = old_afs.keys().chain(new_afs.keys()).collect();
+    let mut changes = Vec::new();
+    for pin in pins {
+        let o = old_afs.get(pin).unwrap_or(&empty);
+        let n = new_afs.get(pin).unwrap_or(&empty);
+        if o != n {
+            changes.push(PinChange {
+                version: new.version.clone(),
+                pin: pin.clone(),
+                added: difference(n.iter(), o.iter()),
+                removed: difference(o.iter(), n.iter()),
+            });
+        }
+    }
+    changes
+}
+
+fn diff_dma(old: &ip::dma::Ip, new: &ip::dma::Ip) -> Result<Vec<RequestChange>> {
+    let requests = |ip: &ip::dma::Ip| -> Result<BTreeMap<String, String>> {
+        let mut requests: BTreeMap<_, _> = get_mode_maps(ip)?
+            .into_iter()
+            .map(|(name, mode)| (name, mode.cr.to_string()))
+            .collect();
+        for (name, request) in dmamux::signals(ip) {
+            requests.insert(name.to_string(), request.to_string());
+        }
+        Ok(requests)
+    };
+    let old_requests = requests(old)?;
+    let new_requests = requests(new)?;
+
+    let names: BTreeSet<_> = old_requests.keys().chain(new_requests.keys()).collect();
+    let mut changes = Vec::new();
+    for name in names {
+        let o = old_requests.get(name);
+        let n = new_requests.get(name);
+        if o != n {
+            changes.push(RequestChange {
+                version: new.version.clone(),
+                request: name.clone(),
+                old: o.cloned(),
+                new: n.cloned(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+fn difference<'a>(
+    a: impl Iterator<Item = &'a String>,
+    b: impl Iterator<Item = &'a String>,
+) -> Vec<String> {
+    let b: BTreeSet<_> = b.collect();
+    a.filter(|x| !b.contains(x)).cloned().collect()
+}
+
+impl FamilyReport {
+    pub fn is_empty(&self) -> bool {
+        self.added_mcus.is_empty() && self.removed_mcus.is_empty() && self.ips.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, family) in &self.families {
+            writeln!(f, "{}", name)?;
+            if !family.added_mcus.is_empty() {
+                writeln!(f, "  MCUs added: {}", family.added_mcus.join(", "))?;
+            }
+            if !family.removed_mcus.is_empty() {
+                writeln!(f, "  MCUs removed: {}", family.removed_mcus.join(", "))?;
+            }
+            for (ip_name, ip) in &family.ips {
+                writeln!(f, "  {}", ip_name)?;
+                for change in &ip.versions {
+                    writeln!(
+                        f,
+                        "    {}: {} -> {}",
+                        change.mcu,
+                        or_none(&change.old.join(", ")),
+                        or_none(&change.new.join(", "))
+                    )?;
+                }
+                for change in &ip.pins {
+                    let afs = change
+                        .added
+                        .iter()
+                        .map(|af| format!("+{}", af))
+                        .chain(change.removed.iter().map(|af| format!("-{}", af)))
+                        .collect::<Vec<_>>();
+                    writeln!(
+                        f,
+                        "    {} {}: {}",
+                        change.version,
+                        change.pin,
+                        afs.join(", ")
+                    )?;
+                }
+                for change in &ip.requests {
+                    writeln!(
+                        f,
+                        "    {} {}: {} -> {}",
+                        change.version,
+                        change.request,
+                        or_none(change.old.as_deref().unwrap_or_default()),
+                        or_none(change.new.as_deref().unwrap_or_default())
+                    )?;
+                }
+                for error in &ip.errors {
+                    writeln!(f, "    error: {}", error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn or_none(s: &str) -> &str {
+    if s.is_empty() {
+        "none"
+    } else {
+        s
+    }
+}
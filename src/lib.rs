@@ -0,0 +1,28 @@
+//! Code generation for the stm32 HAL crates from the STM32CubeMX database.
+//!
+//! - [`cubemx`] loads the MCU database: families, MCU descriptions and IP mode
+//!   files.
+//! - [`codegen`] renders HAL source code, such as GPIO mappings, DMA and ADC
+//!   tables or linker scripts, from the loaded data. Its
+//!   [`generator`](codegen::generator) module lists the available generators.
+//! - [`pinout`], [`diff`] and [`output`] answer pin queries, compare database
+//!   releases and write generated files.
+//!
+//! ```no_run
+//! use codegen::codegen::generator::{self, Options};
+//! use codegen::cubemx::{Db, Selector};
+//!
+//! let db = Db::new("STM32CubeMX/db");
+//! let selector = Selector::new().family("STM32F3");
+//! let gpio = generator::find("gpio").unwrap();
+//! for artifact in generator::run(gpio, &db, &selector, &Options::default())? {
+//!     println!("{}", artifact.content);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod codegen;
+pub mod cubemx;
+pub mod diff;
+pub mod output;
+pub mod pinout;
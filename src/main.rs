@@ -1,64 +1,777 @@
-mod codegen;
-mod cubemx;
-
-use anyhow::Result;
-use cubemx::Db;
-use std::path::PathBuf;
+use ::codegen::{
+    codegen::{
+        dmamux,
+        format::Format,
+        generator::{self, Artifact, Generator, Options},
+        mcu_variants,
+    },
+    cubemx::{self, Db, Selector},
+    diff, output, pinout,
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
-#[structopt(about = "Code generation for the stm32f3xx-hal crate")]
+#[structopt(about = "Code generation for the stm32 HAL crates")]
 enum Command {
-    #[structopt(about = "Generate GPIO mappings from an STM32CubeMX database")]
-    Gpio {
+    #[structopt(
+        about = "Run the generators for every family and write the results to a directory"
+    )]
+    Batch {
+        #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
+        db_path: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "Directory to write the generated files to, one subdirectory per family"
+        )]
+        out_dir: PathBuf,
+
+        #[structopt(
+            long = "generator",
+            number_of_values = 1,
+            possible_values = generator::names(),
+            help = "Run only the given generators"
+        )]
+        generators: Vec<String>,
+
+        #[structopt(flatten)]
+        options: GeneratorOptions,
+
+        #[structopt(
+            long,
+            help = "Only check that the files are up to date, print a diff if they are not"
+        )]
+        check: bool,
+
+        #[structopt(flatten)]
+        selection: Selection,
+    },
+    #[structopt(about = "Show the differences between two STM32CubeMX database releases")]
+    DbDiff {
+        #[structopt(parse(from_os_str), help = "Path of the old STM32CubeMX MCU database")]
+        old_db_path: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "Path of the new STM32CubeMX MCU database")]
+        new_db_path: PathBuf,
+
+        #[structopt(long, help = "Print the report as JSON")]
+        json: bool,
+
+        #[structopt(flatten)]
+        selection: Selection,
+    },
+    #[structopt(about = "Find a conflict-free pin assignment for a set of signals")]
+    SolvePins {
+        #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
+        db_path: PathBuf,
+
+        #[structopt(help = "MCU name, e.g. STM32F303CBTx")]
+        mcu: String,
+
+        #[structopt(
+            required = true,
+            help = "Signals to assign, e.g. USART2_TX USART2_RX or \"USART2_TX, USART2_RX\""
+        )]
+        signals: Vec<String>,
+
+        #[structopt(
+            long = "pin",
+            number_of_values = 1,
+            help = "Require a signal on a pin, adding the signal if it is not listed, e.g. USART2_TX=PA2"
+        )]
+        pinned: Vec<String>,
+
+        #[structopt(
+            long = "exclude",
+            number_of_values = 1,
+            help = "Do not use a pin, e.g. PA13"
+        )]
+        excluded: Vec<String>,
+
+        #[structopt(long, help = "Print the assignment as JSON")]
+        json: bool,
+    },
+    #[structopt(about = "Find the pins that can carry a signal")]
+    FindSignal {
+        #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
+        db_path: PathBuf,
+
+        #[structopt(help = "Signal name, e.g. TIM1_CH1N, or a peripheral, e.g. TIM1")]
+        signal: String,
+
+        #[structopt(long, help = "Print the pins as JSON")]
+        json: bool,
+
+        #[structopt(flatten)]
+        selection: Selection,
+    },
+    #[structopt(about = "Show the functions of a pin of an MCU")]
+    PinInfo {
         #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
         db_path: PathBuf,
 
-        fname: String,
+        #[structopt(help = "MCU name, e.g. STM32F303CBTx")]
+        mcu: String,
+
+        #[structopt(help = "Pin name, e.g. PA13")]
+        pin: String,
+
+        #[structopt(long, help = "Print the pin functions as JSON")]
+        json: bool,
     },
-    #[structopt(about = "Generate F4-like DMA tables")]
-    Dma {
+    #[structopt(about = "Show the properties of a single MCU")]
+    ShowMcu {
         #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
         db_path: PathBuf,
 
-        fname: String,
+        #[structopt(help = "MCU name, e.g. STM32F303CBTx or STM32F303C(B-C)Tx")]
+        name: String,
+
+        #[structopt(
+            long,
+            possible_values = Format::NAMES,
+            help = "Print the properties in this format instead, one record per MCU variant"
+        )]
+        format: Option<Format>,
+
+        #[structopt(flatten)]
+        output: Output,
     },
 }
 
+/// Arguments of the subcommands running a single generator, one subcommand per
+/// entry of `GENERATORS`.
+#[derive(StructOpt)]
+struct Generate {
+    #[structopt(parse(from_os_str), help = "Path of the STM32CubeMX MCU database")]
+    db_path: PathBuf,
+
+    #[structopt(flatten)]
+    options: GeneratorOptions,
+
+    #[structopt(flatten)]
+    selection: Selection,
+
+    #[structopt(flatten)]
+    output: Output,
+}
+
+// Options of the generators, shared by `batch` and the generator subcommands.
+// Not a doc comment, structopt would take it as the about text of `batch`.
+#[derive(StructOpt)]
+struct GeneratorOptions {
+    #[structopt(
+        long,
+        help = "Write one file per HAL feature instead of per MCU, for generators writing several files"
+    )]
+    per_feature: bool,
+
+    #[structopt(
+        long,
+        possible_values = Format::NAMES,
+        help = "Emit the data behind the code in this format instead"
+    )]
+    format: Option<Format>,
+
+    #[structopt(long, help = "Write documents as HTML instead of Markdown")]
+    html: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "TOML file with the output type and NoPin rules of the GPIO pin types"
+    )]
+    rules: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Template file to render the GPIO mappings with"
+    )]
+    template: Option<PathBuf>,
+
+    #[structopt(
+        long = "dma-header",
+        number_of_values = 1,
+        help = "HAL DMA header defining the DMAMUX request lines, as PATH or FAMILY=PATH, \
+                e.g. stm32h7xx_hal_dma.h"
+    )]
+    dma_headers: Vec<dmamux::Header>,
+}
+
+impl GeneratorOptions {
+    fn options(self) -> Options {
+        Options {
+            per_feature: self.per_feature,
+            format: self.format,
+            html: self.html,
+            rules: self.rules,
+            template: self.template,
+            dma_headers: self.dma_headers,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct Selection {
+    #[structopt(
+        long = "family",
+        number_of_values = 1,
+        help = "Select the MCUs of a family, e.g. STM32F3"
+    )]
+    families: Vec<String>,
+
+    #[structopt(
+        long = "subfamily",
+        number_of_values = 1,
+        help = "Select the MCUs of a sub-family, e.g. STM32F303"
+    )]
+    sub_families: Vec<String>,
+
+    #[structopt(
+        long = "mcu",
+        number_of_values = 1,
+        help = "Select the MCUs whose name matches a glob pattern, e.g. STM32F303*"
+    )]
+    mcus: Vec<String>,
+
+    #[structopt(
+        long = "mcu-regex",
+        number_of_values = 1,
+        help = "Select the MCUs whose name matches a regular expression"
+    )]
+    mcu_regexes: Vec<String>,
+
+    #[structopt(
+        long = "package",
+        number_of_values = 1,
+        help = "Select the MCUs in a package, e.g. LQFP48"
+    )]
+    packages: Vec<String>,
+
+    #[structopt(
+        long = "core",
+        number_of_values = 1,
+        help = "Select the MCUs with a core, e.g. Cortex-M4"
+    )]
+    cores: Vec<String>,
+}
+
+impl Selection {
+    fn selector(&self) -> Result<Selector> {
+        self.selector_for(&self.families)
+    }
+
+    fn selector_for(&self, families: &[String]) -> Result<Selector> {
+        let mut selector = Selector::new();
+        for family in families {
+            selector = selector.family(family);
+        }
+        for sub_family in &self.sub_families {
+            selector = selector.sub_family(sub_family);
+        }
+        for glob in &self.mcus {
+            selector = selector.mcu_glob(glob)?;
+        }
+        for re in &self.mcu_regexes {
+            selector = selector.mcu_regex(re)?;
+        }
+        for package in &self.packages {
+            selector = selector.package(package);
+        }
+        for core in &self.cores {
+            selector = selector.core(core);
+        }
+        Ok(selector)
+    }
+}
+
+#[derive(StructOpt)]
+struct Output {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Replace the generated region of this file, or write the files to this directory, instead of printing to stdout"
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Name of the region in the output file, defaults to the subcommand name"
+    )]
+    region: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Only check that the output is up to date, print a diff if it is not"
+    )]
+    check: bool,
+}
+
+impl Output {
+    /// Prints the artifacts of a command or writes them to the output.
+    ///
+    /// Commands writing a single text update the region between
+    /// `// codegen:begin <region>` and `// codegen:end` of the output file,
+    /// the others (`files`) write their files into the output directory. Data
+    /// formats replace the whole output file.
+    fn write(
+        &self,
+        name: &str,
+        files: bool,
+        format: Option<Format>,
+        artifacts: &[Artifact],
+    ) -> Result<()> {
+        let path = match &self.output {
+            Some(path) => path,
+            None if self.check => bail!("--check requires --output"),
+            None => match artifacts {
+                [artifact] => {
+                    print!("{}", artifact.content);
+                    return Ok(());
+                }
+                _ => bail!(
+                    "{} writes {} files, choose a directory with --output",
+                    name,
+                    artifacts.len()
+                ),
+            },
+        };
+        let files = files && format.is_none();
+
+        let mut stale = 0;
+        for artifact in artifacts {
+            let differs = if files {
+                output::write_file(&path.join(&artifact.path), &artifact.content, self.check)?
+            } else if format.is_some() {
+                output::write_file(path, &artifact.content, self.check)?
+            } else {
+                let region = self.region.as_deref().unwrap_or(name);
+                output::write_region(path, region, &artifact.content, self.check)?
+            };
+            if differs {
+                stale += 1;
+            }
+        }
+
+        if self.check && stale > 0 {
+            if files {
+                bail!("{} files are out of date", stale);
+            }
+            bail!("{:?} is out of date", path);
+        }
+        Ok(())
+    }
+}
+
 fn main() -> Result<()> {
-    match Command::from_args() {
-        Command::Gpio { db_path, fname } => handle_gpio(db_path, &fname),
-        Command::Dma { db_path, fname } => handle_dma(db_path, &fname),
+    let mut app = Command::clap();
+    for generator in generator::GENERATORS {
+        app = app.subcommand(
+            Generate::clap()
+                .name(generator.name())
+                .about(generator.about()),
+        );
+    }
+    let matches = app.get_matches();
+    if let (name, Some(matches)) = matches.subcommand() {
+        if let Some(generator) = generator::find(name) {
+            return handle_generate(generator, Generate::from_clap(matches));
+        }
+    }
+
+    match Command::from_clap(&matches) {
+        Command::Batch {
+            db_path,
+            out_dir,
+            generators,
+            options,
+            check,
+            selection,
+        } => handle_batch(
+            db_path,
+            &out_dir,
+            &generators,
+            &options.options(),
+            check,
+            &selection,
+        ),
+        Command::DbDiff {
+            old_db_path,
+            new_db_path,
+            json,
+            selection,
+        } => handle_db_diff(old_db_path, new_db_path, json, &selection.selector()?),
+        Command::SolvePins {
+            db_path,
+            mcu,
+            signals,
+            pinned,
+            excluded,
+            json,
+        } => handle_solve_pins(db_path, &mcu, &signals, &pinned, &excluded, json),
+        Command::FindSignal {
+            db_path,
+            signal,
+            json,
+            selection,
+        } => handle_find_signal(db_path, &signal, json, &selection.selector()?),
+        Command::PinInfo {
+            db_path,
+            mcu,
+            pin,
+            json,
+        } => handle_pin_info(db_path, &mcu, &pin, json),
+        Command::ShowMcu {
+            db_path,
+            name,
+            format,
+            output,
+        } => handle_show_mcu(db_path, &name, format, &output),
+    }
+}
+
+fn handle_generate(generator: &dyn Generator, args: Generate) -> Result<()> {
+    let db = cubemx::Db::new(args.db_path);
+    let options = args.options.options();
+    let artifacts = generator::run(generator, &db, &args.selection.selector()?, &options)?;
+    args.output.write(
+        generator.name(),
+        generator.writes_files(),
+        options.format,
+        &artifacts,
+    )
+}
+
+fn handle_db_diff(
+    old_db_path: PathBuf,
+    new_db_path: PathBuf,
+    json: bool,
+    selector: &Selector,
+) -> Result<()> {
+    let old = cubemx::Db::new(old_db_path);
+    let new = cubemx::Db::new(new_db_path);
+
+    let report = diff::diff(&old, &new, selector)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
     }
+    Ok(())
 }
 
-fn handle_gpio(db_path: PathBuf, fname: &str) -> Result<()> {
+fn handle_solve_pins(
+    db_path: PathBuf,
+    mcu: &str,
+    signals: &[String],
+    pinned: &[String],
+    excluded: &[String],
+    json: bool,
+) -> Result<()> {
     let db = cubemx::Db::new(db_path);
+    let pinout = pinout::Pinout::load(&db, mcu)?;
 
-    emit_autogen_comment(&db)?;
+    let signals: Vec<_> = signals
+        .iter()
+        .flat_map(|s| s.split(','))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    let mut fixed = BTreeMap::new();
+    for p in pinned {
+        match p.split_once('=') {
+            Some((signal, pin)) => fixed.insert(signal.trim().into(), pin.trim().into()),
+            None => bail!("invalid pin requirement `{}`, expected SIGNAL=PIN", p),
+        };
+    }
+    let excluded: BTreeSet<_> = excluded.iter().cloned().collect();
+    for pin in fixed.values().chain(&excluded) {
+        if pinout.pin(pin).is_none() {
+            bail!("{} has no pin {}", pinout.mcu, pin);
+        }
+    }
 
-    let gpio_ips = cubemx::load_f3_gpio_ips(&db, fname)?;
-    codegen::gpio::gen_mappings(&gpio_ips)?;
+    let assignments = pinout::solve(&pinout, &signals, &fixed, &excluded)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&assignments)?);
+        return Ok(());
+    }
 
+    println!("{} ({})", pinout.mcu, pinout.package);
+    for a in &assignments {
+        let af = match a.af {
+            Some(af) => format!("AF{}", af),
+            None => "additional".to_string(),
+        };
+        println!("  {:<16} {:<6} {:<5} {}", a.signal, a.pin, a.position, af);
+    }
     Ok(())
 }
 
-fn handle_dma(db_path: PathBuf, fname: &str) -> Result<()> {
+fn handle_find_signal(
+    db_path: PathBuf,
+    signal: &str,
+    json: bool,
+    selector: &Selector,
+) -> Result<()> {
     let db = cubemx::Db::new(db_path);
+    let pinouts: Result<Vec<_>> = cubemx::load_mcus(&db, selector)?
+        .into_iter()
+        .map(|mcu| pinout::Pinout::from_mcu(&db, mcu))
+        .collect();
+
+    let locations = pinout::find_signal(&pinouts?, signal);
+    if locations.is_empty() {
+        bail!("no pin of the selected MCUs carries {}", signal);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&locations)?);
+        return Ok(());
+    }
 
-    emit_autogen_comment(&db)?;
+    for l in &locations {
+        let af = match l.af {
+            Some(af) => format!("AF{}", af),
+            None => "additional".to_string(),
+        };
+        println!("{:<16} {:<6} {}", l.signal, l.pin, af);
+        for m in &l.mcus {
+            println!("  {} ({}, pin {})", m.mcu, m.package, m.position);
+        }
+    }
+    Ok(())
+}
 
-    let dma_maps: Result<_> = cubemx::load_f3_dma_ips(&db, fname)?
+fn handle_pin_info(db_path: PathBuf, mcu: &str, pin: &str, json: bool) -> Result<()> {
+    let db = cubemx::Db::new(db_path);
+    let info = pinout::pin_info(&db, mcu, pin)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} on {} ({}, pin {})",
+        info.pin, info.mcu, info.package, info.position
+    );
+    let reset = if info.debug_at_reset {
+        "debug function (AF0)"
+    } else {
+        "GPIO"
+    };
+    println!("  Reset:      {}", reset);
+    let list = |values: &[String]| {
+        if values.is_empty() {
+            "-".to_string()
+        } else {
+            values.join(", ")
+        }
+    };
+    for (af, signals) in info.afs.iter().enumerate() {
+        println!("  AF{:<2}        {}", af, list(signals));
+    }
+    println!("  Additional: {}", list(&info.additional));
+    let packages: Vec<_> = info
+        .packages
         .iter()
-        .map(crate::codegen::dma::ip_to_table)
+        .map(|m| format!("{} ({}, pin {})", m.package, m.mcu, m.position))
         .collect();
-    crate::codegen::dma::print_table(&dma_maps?);
+    println!("  Packages:   {}", list(&packages));
     Ok(())
 }
 
-fn emit_autogen_comment(db: &Db) -> Result<()> {
-    let package = cubemx::package::load(db)?;
-    codegen::gen_autogen_comment(&package);
+/// The properties of an MCU variant, as emitted by `show-mcu --format`.
+#[derive(Serialize)]
+struct McuRecord {
+    mcu: String,
+    /// Cores, separated by `|`.
+    cores: String,
+    /// Maximum frequency in MHz of every core, separated by `|`.
+    frequencies: String,
+    /// Sizes in KiB.
+    flash: u32,
+    ram: u32,
+    ccm_ram: Option<u32>,
+    package: String,
+    ios: u32,
+    voltage_min: Option<f32>,
+    voltage_max: Option<f32>,
+    temperature_min: Option<f32>,
+    temperature_max: Option<f32>,
+}
+
+fn mcu_records(mcu: &cubemx::mcu::Mcu) -> Vec<McuRecord> {
+    let range = |range: &Option<cubemx::mcu::Range>| match range {
+        Some(range) => (range.min, range.max),
+        None => (None, None),
+    };
+    let (voltage_min, voltage_max) = range(&mcu.voltage);
+    let (temperature_min, temperature_max) = range(&mcu.temperature);
+    let frequencies: Vec<_> = mcu.frequencies.iter().map(ToString::to_string).collect();
+
+    mcu_variants(&mcu.ref_name)
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            // Sizes are listed per variant, or once for all of them.
+            let pick = |sizes: &[u32]| sizes.get(i).or_else(|| sizes.last()).copied();
+            McuRecord {
+                mcu: name,
+                cores: mcu.cores.join("|"),
+                frequencies: frequencies.join("|"),
+                flash: pick(&mcu.flash).unwrap_or_default(),
+                ram: pick(&mcu.ram).unwrap_or_default(),
+                ccm_ram: pick(&mcu.ccm_ram),
+                package: mcu.package.clone(),
+                ios: mcu.io_nb,
+                voltage_min,
+                voltage_max,
+                temperature_min,
+                temperature_max,
+            }
+        })
+        .collect()
+}
+
+fn handle_show_mcu(
+    db_path: PathBuf,
+    name: &str,
+    format: Option<Format>,
+    output: &Output,
+) -> Result<()> {
+    let db = cubemx::Db::new(db_path);
+    let mcu = cubemx::load_mcu(&db, name)?;
+
+    if let Some(format) = format {
+        let release = cubemx::package::load(&db)?.pack_description.release;
+        let content = format.render(&release, &mcu_records(&mcu))?;
+        let artifact = Artifact::new(format!("show-mcu.{}", format.extension()), content);
+        return output.write("show-mcu", false, Some(format), &[artifact]);
+    }
+
+    let join = |values: &[u32]| {
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" / ")
+    };
+    let range = |range: &Option<cubemx::mcu::Range>, unit: &str| match range {
+        Some(cubemx::mcu::Range {
+            min: Some(min),
+            max: Some(max),
+        }) => format!("{min} {unit} .. {max} {unit}"),
+        _ => "unknown".to_string(),
+    };
+
+    let mut content = format!("{}\n", mcu.ref_name);
+    content.push_str(&format!("  Core:        {}\n", mcu.cores.join(" + ")));
+    content.push_str(&format!("  Frequency:   {} MHz\n", join(&mcu.frequencies)));
+    content.push_str(&format!("  Flash:       {} KiB\n", join(&mcu.flash)));
+    content.push_str(&format!("  RAM:         {} KiB\n", join(&mcu.ram)));
+    if !mcu.ccm_ram.is_empty() {
+        content.push_str(&format!("  CCM RAM:     {} KiB\n", join(&mcu.ccm_ram)));
+    }
+    content.push_str(&format!("  Package:     {}\n", mcu.package));
+    content.push_str(&format!("  I/Os:        {}\n", mcu.io_nb));
+    content.push_str(&format!("  Voltage:     {}\n", range(&mcu.voltage, "V")));
+    content.push_str(&format!(
+        "  Temperature: {}\n",
+        range(&mcu.temperature, "°C")
+    ));
+    output.write(
+        "show-mcu",
+        false,
+        None,
+        &[Artifact::new("show-mcu", content)],
+    )
+}
 
+fn handle_batch(
+    db_path: PathBuf,
+    out_dir: &Path,
+    generators: &[String],
+    options: &Options,
+    check: bool,
+    selection: &Selection,
+) -> Result<()> {
+    let db = cubemx::Db::new(db_path);
+    let families = cubemx::families::load(&db)?;
+
+    let generators: Vec<_> = generator::GENERATORS
+        .iter()
+        .copied()
+        .filter(|g| generators.is_empty() || generators.iter().any(|name| name == g.name()))
+        .collect();
+    let mut failed = 0;
+    let mut stale = 0;
+    let wanted = selection
+        .families
+        .iter()
+        .fold(Selector::new(), |selector, family| selector.family(family));
+
+    for family in &families.families {
+        if !wanted.matches_family(family) {
+            continue;
+        }
+        let selector = selection.selector_for(std::slice::from_ref(&family.name))?;
+        if selector.select(&families).is_empty() {
+            continue;
+        }
+
+        let dir = out_dir.join(family.name.to_lowercase());
+        for generator in &generators {
+            eprintln!("{}: {}", family.name, generator.name());
+            match run_generator(&db, *generator, &selector, options, &dir, check) {
+                Ok(n) => stale += n,
+                Err(e) => {
+                    eprintln!("{}: {} failed: {:#}", family.name, generator.name(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} generator runs failed", failed);
+    }
+    if check && stale > 0 {
+        bail!("{} files are out of date", stale);
+    }
     Ok(())
 }
+
+/// Runs a generator for `batch`, multi-file generators get a subdirectory of
+/// their own.
+fn run_generator(
+    db: &Db,
+    generator: &dyn Generator,
+    selector: &Selector,
+    options: &Options,
+    dir: &Path,
+    check: bool,
+) -> Result<usize> {
+    let artifacts = generator::run(generator, db, selector, options)?;
+    let dir = if generator.writes_files() && options.format.is_none() {
+        dir.join(generator.name())
+    } else {
+        dir.to_path_buf()
+    };
+
+    let mut stale = 0;
+    for artifact in artifacts {
+        if output::write_file(&dir.join(&artifact.path), &artifact.content, check)? {
+            stale += 1;
+        }
+    }
+    Ok(stale)
+}
@@ -0,0 +1,50 @@
+use crate::codegen::region;
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::{fs, io::ErrorKind, path::Path};
+
+/// Writes `content` to a file, or with `check` only compares it to the file
+/// and prints a unified diff if they differ.
+///
+/// Returns whether the file content differed from `content`.
+pub fn write_file(path: &Path, content: &str, check: bool) -> Result<bool> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("cannot read file: {:?}", path)),
+    };
+    update(path, &source, content, check)
+}
+
+/// Like [`write_file`], but only replaces the lines between
+/// `// codegen:begin <name>` and `// codegen:end` of an existing file.
+pub fn write_region(path: &Path, name: &str, content: &str, check: bool) -> Result<bool> {
+    let source =
+        fs::read_to_string(path).with_context(|| format!("cannot read file: {:?}", path))?;
+    let replaced = region::replace(&source, name, content)
+        .with_context(|| format!("cannot update file: {:?}", path))?;
+    update(path, &source, &replaced, check)
+}
+
+fn update(path: &Path, old: &str, new: &str, check: bool) -> Result<bool> {
+    if old == new {
+        return Ok(false);
+    }
+
+    if check {
+        let name = path.display().to_string();
+        print!(
+            "{}",
+            TextDiff::from_lines(old, new)
+                .unified_diff()
+                .header(&name, &name)
+        );
+    } else {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("cannot create directory: {:?}", dir))?;
+        }
+        fs::write(path, new).with_context(|| format!("cannot write file: {:?}", path))?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,487 @@
+use crate::cubemx::{
+    self,
+    ip::gpio,
+    mcu::{self, PinName},
+    Db,
+};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The functions of every GPIO of an MCU in its package.
+#[derive(Debug)]
+pub struct Pinout {
+    pub mcu: String,
+    pub package: String,
+    pub pins: Vec<PinFunctions>,
+}
+
+#[derive(Debug)]
+pub struct PinFunctions {
+    pub name: PinName,
+    pub position: String,
+    /// Alternate functions from the GPIO IP, as (AF number, signal).
+    pub afs: Vec<(u8, String)>,
+    /// Signals of the pin that are not selected by an alternate function,
+    /// e.g. analog inputs.
+    pub additional: Vec<String>,
+}
+
+impl Pinout {
+    /// Loads the pinout of an MCU by its reference name or description file.
+    pub fn load(db: &Db, mcu_name: &str) -> Result<Self> {
+        Self::from_mcu(db, cubemx::load_mcu(db, mcu_name)?)
+    }
+
+    pub fn from_mcu(db: &Db, mcu: mcu::Mcu) -> Result<Self> {
+        let gpio_ip = mcu
+            .ips
+            .iter()
+            .find(|ip| ip.name == "GPIO")
+            .with_context(|| format!("{} has no GPIO IP", mcu.ref_name))?;
+        let gpio_ip = gpio::load(db, &gpio_ip.version)?;
+
+        let mut afs = BTreeMap::<(char, u8), Vec<(u8, String)>>::new();
+        for pin in &gpio_ip.pins {
+            let name = match PinName::parse(&pin.name) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let entry = afs.entry((name.port, name.number)).or_default();
+            for signal in &pin.pin_signals {
+                if let Ok(af) = signal.af() {
+                    entry.push(af);
+                }
+            }
+        }
+
+        let mut pins = BTreeMap::<(char, u8), PinFunctions>::new();
+        for pin in &mcu.pins {
+            let name = match pin.gpio() {
+                Some(name) => name,
+                None => continue,
+            };
+            let key = (name.port, name.number);
+            let pin_afs = afs.get(&key).cloned().unwrap_or_default();
+            let functions = pins.entry(key).or_insert_with(|| PinFunctions {
+                name: PinName {
+                    suffix: None,
+                    ..name
+                },
+                position: pin.position.clone(),
+                afs: pin_afs,
+                additional: Vec::new(),
+            });
+            for signal in &pin.signals {
+                if signal.name != "GPIO"
+                    && !functions.afs.iter().any(|(_, s)| *s == signal.name)
+                    && !functions.additional.contains(&signal.name)
+                {
+                    functions.additional.push(signal.name.clone());
+                }
+            }
+        }
+
+        let mut pins: Vec<_> = pins.into_values().collect();
+        for pin in &mut pins {
+            pin.afs.sort();
+            pin.afs.dedup();
+            pin.additional.sort();
+        }
+
+        Ok(Self {
+            mcu: mcu.ref_name,
+            package: mcu.package,
+            pins,
+        })
+    }
+
+    /// Returns the pins that can carry `signal`, with the AF selecting it.
+    /// Signal names are not case-sensitive.
+    pub fn candidates(&self, signal: &str) -> Vec<(&PinFunctions, Option<u8>)> {
+        let mut candidates = Vec::new();
+        for pin in &self.pins {
+            if let Some((af, _)) = pin.afs.iter().find(|(_, s)| s.eq_ignore_ascii_case(signal)) {
+                candidates.push((pin, Some(*af)));
+            } else if pin
+                .additional
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(signal))
+            {
+                candidates.push((pin, None));
+            }
+        }
+        candidates
+    }
+
+    /// Returns a GPIO by name, e.g. `PA2` or `pa2`.
+    pub fn pin(&self, name: &str) -> Option<&PinFunctions> {
+        self.pins
+            .iter()
+            .find(|p| p.name.to_string().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A pin that can carry a signal and the MCUs exposing it.
+#[derive(Debug, Serialize)]
+pub struct Location {
+    pub signal: String,
+    pub pin: String,
+    /// The alternate function to select, `None` for additional functions.
+    pub af: Option<u8>,
+    pub mcus: Vec<McuPin>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct McuPin {
+    pub mcu: String,
+    pub package: String,
+    pub position: String,
+}
+
+/// Finds every pin of the given pinouts that can carry a signal.
+///
+/// `query` is either a full signal name such as `TIM1_CH1N`, or a peripheral
+/// such as `TIM1` to find all of its signals.
+pub fn find_signal(pinouts: &[Pinout], query: &str) -> Vec<Location> {
+    let query = query.to_uppercase();
+    let matches = |signal: &str| {
+        signal == query || matches!(gpio::split_signal(signal), Some((per, _)) if per == query)
+    };
+
+    let mut locations = BTreeMap::<(&str, &PinName, Option<u8>), Vec<McuPin>>::new();
+    for pinout in pinouts {
+        for pin in &pinout.pins {
+            let afs = pin.afs.iter().map(|(af, s)| (Some(*af), s));
+            let additional = pin.additional.iter().map(|s| (None, s));
+            for (af, signal) in afs.chain(additional) {
+                if matches(signal) {
+                    locations
+                        .entry((signal, &pin.name, af))
+                        .or_default()
+                        .push(McuPin {
+                            mcu: pinout.mcu.clone(),
+                            package: pinout.package.clone(),
+                            position: pin.position.clone(),
+                        });
+                }
+            }
+        }
+    }
+
+    locations
+        .into_iter()
+        .map(|((signal, pin, af), mcus)| Location {
+            signal: signal.into(),
+            pin: pin.to_string(),
+            af,
+            mcus,
+        })
+        .collect()
+}
+
+/// Everything known about one pin of an MCU.
+#[derive(Debug, Serialize)]
+pub struct PinInfo {
+    pub mcu: String,
+    pub package: String,
+    pub pin: String,
+    pub position: String,
+    /// Whether the pin starts in its debug function rather than as a GPIO.
+    pub debug_at_reset: bool,
+    /// Signals selected by AF0 to AF15.
+    pub afs: Vec<Vec<String>>,
+    pub additional: Vec<String>,
+    /// MCUs of the same sub-family exposing the pin.
+    pub packages: Vec<McuPin>,
+}
+
+/// Collects the functions of a pin of an MCU and the packages exposing it.
+pub fn pin_info(db: &Db, mcu_name: &str, pin_name: &str) -> Result<PinInfo> {
+    let pinout = Pinout::load(db, mcu_name)?;
+    let pin = PinName::parse(&pin_name.to_uppercase())?;
+    let functions = pinout
+        .pins
+        .iter()
+        .find(|p| p.name.port == pin.port && p.name.number == pin.number)
+        .with_context(|| format!("{} has no pin {}", pinout.mcu, pin))?;
+
+    let mut afs = vec![Vec::new(); 16];
+    for (af, signal) in &functions.afs {
+        afs.get_mut(usize::from(*af))
+            .with_context(|| format!("invalid alternate function AF{}", af))?
+            .push(signal.clone());
+    }
+
+    let families = cubemx::families::load(db)?;
+    let sub_family = families
+        .families
+        .iter()
+        .flat_map(|family| family.sub_families.iter())
+        .find(|sub_family| {
+            sub_family
+                .mcus
+                .iter()
+                .any(|mcu_| mcu_.name == mcu_name || mcu_.ref_name == mcu_name)
+        })
+        .with_context(|| format!("MCU {} not found", mcu_name))?;
+    let mut names: Vec<_> = sub_family.mcus.iter().map(|mcu_| &mcu_.name).collect();
+    names.sort();
+    names.dedup();
+
+    let mut packages = Vec::new();
+    for name in names {
+        let mcu = mcu::load(db, name)?;
+        let position = mcu.pins.iter().find_map(|p| match p.gpio() {
+            Some(n) if n.port == pin.port && n.number == pin.number => Some(p.position.clone()),
+            _ => None,
+        });
+        if let Some(position) = position {
+            packages.push(McuPin {
+                mcu: mcu.ref_name,
+                package: mcu.package,
+                position,
+            });
+        }
+    }
+
+    Ok(PinInfo {
+        mcu: pinout.mcu.clone(),
+        package: pinout.package.clone(),
+        pin: functions.name.to_string(),
+        position: functions.position.clone(),
+        debug_at_reset: gpio::is_debug_pin(pin.port, pin.number),
+        afs,
+        additional: functions.additional.clone(),
+        packages,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Assignment {
+    pub signal: String,
+    pub pin: String,
+    pub position: String,
+    /// The alternate function to select, `None` for additional functions.
+    pub af: Option<u8>,
+}
+
+/// Assigns every signal to a pin that can carry it, using each pin at most
+/// once.
+///
+/// `fixed` maps signals to the pin they must use, signals missing from
+/// `signals` are assigned as well. Pins in `excluded` are not used at all.
+/// Among several solutions, pins earlier in the pinout are preferred for
+/// signals earlier in `signals`. Signal and pin names are not case-sensitive.
+pub fn solve(
+    pinout: &Pinout,
+    signals: &[String],
+    fixed: &BTreeMap<String, String>,
+    excluded: &BTreeSet<String>,
+) -> Result<Vec<Assignment>> {
+    let fixed: BTreeMap<_, _> = fixed
+        .iter()
+        .map(|(signal, pin)| (signal.to_uppercase(), pin.to_uppercase()))
+        .collect();
+    let excluded: BTreeSet<_> = excluded.iter().map(|pin| pin.to_uppercase()).collect();
+    let mut signals: Vec<_> = signals.iter().map(|s| s.to_uppercase()).collect();
+    for signal in fixed.keys() {
+        if !signals.contains(signal) {
+            signals.push(signal.clone());
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for signal in &signals {
+        let mut pins: Vec<_> = pinout
+            .candidates(signal)
+            .into_iter()
+            .filter(|(pin, _)| !excluded.contains(&pin.name.to_string()))
+            .collect();
+        if let Some(wanted) = fixed.get(signal) {
+            pins.retain(|(pin, _)| pin.name.to_string() == *wanted);
+            if pins.is_empty() {
+                bail!(
+                    "{} cannot be routed to {} on {}",
+                    signal,
+                    wanted,
+                    pinout.mcu
+                );
+            }
+        }
+        if pins.is_empty() {
+            bail!("no available pin of {} carries {}", pinout.mcu, signal);
+        }
+        candidates.push(pins);
+    }
+
+    // Bipartite matching of signals to pins with augmenting paths.
+    let mut owner = BTreeMap::<String, usize>::new();
+    for (signal, name) in signals.iter().enumerate() {
+        let mut visited = BTreeSet::new();
+        if !augment(signal, &candidates, &mut owner, &mut visited) {
+            bail!("no conflict-free assignment of {} on {}", name, pinout.mcu);
+        }
+    }
+
+    let mut assignments: Vec<_> = owner
+        .into_iter()
+        .map(|(pin, signal)| {
+            let (functions, af) = candidates[signal]
+                .iter()
+                .find(|(p, _)| p.name.to_string() == pin)
+                .unwrap();
+            (
+                signal,
+                Assignment {
+                    signal: signals[signal].clone(),
+                    pin,
+                    position: functions.position.clone(),
+                    af: *af,
+                },
+            )
+        })
+        .collect();
+    assignments.sort_by_key(|(signal, _)| *signal);
+
+    Ok(assignments.into_iter().map(|(_, a)| a).collect())
+}
+
+fn augment(
+    signal: usize,
+    candidates: &[Vec<(&PinFunctions, Option<u8>)>],
+    owner: &mut BTreeMap<String, usize>,
+    visited: &mut BTreeSet<String>,
+) -> bool {
+    for (pin, _) in &candidates[signal] {
+        let pin = pin.name.to_string();
+        if !visited.insert(pin.clone()) {
+            continue;
+        }
+        let free = match owner.get(&pin) {
+            None => true,
+            Some(&other) => augment(other, candidates, owner, visited),
+        };
+        if free {
+            owner.insert(pin, signal);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(name: &str, afs: &[(u8, &str)], additional: &[&str]) -> PinFunctions {
+        PinFunctions {
+            name: PinName::parse(name).unwrap(),
+            position: String::new(),
+            afs: afs.iter().map(|(af, s)| (*af, s.to_string())).collect(),
+            additional: additional.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn pinout() -> Pinout {
+        Pinout {
+            mcu: "STM32F303CBTx".into(),
+            package: "LQFP48".into(),
+            pins: vec![
+                pin("PA2", &[(7, "USART2_TX")], &["ADC1_IN3"]),
+                pin("PA3", &[(7, "USART2_RX")], &["ADC1_IN4"]),
+                pin("PA14", &[(7, "USART2_TX")], &[]),
+                pin("PA15", &[(7, "USART2_RX")], &[]),
+            ],
+        }
+    }
+
+    fn signals(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn pins(assignments: &[Assignment]) -> Vec<(&str, &str)> {
+        assignments
+            .iter()
+            .map(|a| (a.signal.as_str(), a.pin.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn prefers_earlier_pins() {
+        let signals = signals(&["USART2_TX", "USART2_RX"]);
+        let assignments = solve(&pinout(), &signals, &BTreeMap::new(), &BTreeSet::new()).unwrap();
+        assert_eq!(
+            pins(&assignments),
+            [("USART2_TX", "PA2"), ("USART2_RX", "PA3")]
+        );
+        assert_eq!(assignments[0].af, Some(7));
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let signals = signals(&["ADC1_IN3", "USART2_TX"]);
+        let assignments = solve(&pinout(), &signals, &BTreeMap::new(), &BTreeSet::new()).unwrap();
+        assert_eq!(
+            pins(&assignments),
+            [("ADC1_IN3", "PA2"), ("USART2_TX", "PA14")]
+        );
+        assert_eq!(assignments[0].af, None);
+    }
+
+    #[test]
+    fn forced_pin() {
+        let signals = signals(&["USART2_TX"]);
+        let fixed = BTreeMap::from([("USART2_TX".to_string(), "PA14".to_string())]);
+        let assignments = solve(&pinout(), &signals, &fixed, &BTreeSet::new()).unwrap();
+        assert_eq!(pins(&assignments), [("USART2_TX", "PA14")]);
+    }
+
+    #[test]
+    fn forced_pin_of_unlisted_signal() {
+        let signals = signals(&["USART2_TX"]);
+        let fixed = BTreeMap::from([("USART2_RX".to_string(), "PA15".to_string())]);
+        let assignments = solve(&pinout(), &signals, &fixed, &BTreeSet::new()).unwrap();
+        assert_eq!(
+            pins(&assignments),
+            [("USART2_TX", "PA2"), ("USART2_RX", "PA15")]
+        );
+    }
+
+    #[test]
+    fn forced_pin_without_signal() {
+        let signals = signals(&["USART2_TX"]);
+        let fixed = BTreeMap::from([("USART2_TX".to_string(), "PA3".to_string())]);
+        assert!(solve(&pinout(), &signals, &fixed, &BTreeSet::new()).is_err());
+    }
+
+    #[test]
+    fn excluded_pin() {
+        let signals = signals(&["USART2_TX", "USART2_RX"]);
+        let excluded = BTreeSet::from(["PA2".to_string()]);
+        let assignments = solve(&pinout(), &signals, &BTreeMap::new(), &excluded).unwrap();
+        assert_eq!(
+            pins(&assignments),
+            [("USART2_TX", "PA14"), ("USART2_RX", "PA3")]
+        );
+    }
+
+    #[test]
+    fn ignores_case() {
+        let signals = signals(&["usart2_tx", "Usart2_Rx"]);
+        let fixed = BTreeMap::from([("usart2_rx".to_string(), "pa15".to_string())]);
+        let excluded = BTreeSet::from(["pa2".to_string()]);
+        let assignments = solve(&pinout(), &signals, &fixed, &excluded).unwrap();
+        assert_eq!(
+            pins(&assignments),
+            [("USART2_TX", "PA14"), ("USART2_RX", "PA15")]
+        );
+    }
+
+    #[test]
+    fn no_assignment() {
+        let signals = signals(&["USART2_TX", "ADC1_IN3", "ADC1_IN4"]);
+        let excluded = BTreeSet::from(["PA14".to_string()]);
+        assert!(solve(&pinout(), &signals, &BTreeMap::new(), &excluded).is_err());
+    }
+}